@@ -1,17 +1,55 @@
-//use erl_shared::fterm::FTerm;
+use erl_shared::etf;
+use erl_shared::fterm::FTerm;
 use std::fs::File;
 use std::io::Read;
 use erl_aotc_parser::parse_nodot;
 use kernel::parse::process_module;
 
 
+/// Compile a kernel module dump, either `~p` text (`.kernel.term`) or
+/// `term_to_binary` output (`.kernel.etf`).
 pub fn compile(filename: &str) {
   println!("aotc: Reading file {}", filename);
   let mut file = File::open(filename).unwrap();
+
+  let mod_root = if filename.ends_with(".etf") {
+    read_etf(filename, &mut file)
+  } else {
+    read_text(filename, &mut file)
+  };
+
+  //println!("Parsed: {:?}", out_term)
+  if let Some(m) = mod_root {
+    if let Err(e) = process_module(&m) {
+      println!("aotc: {}: {}", filename, e);
+    }
+  }
+}
+
+
+fn read_text(filename: &str, file: &mut File) -> Option<FTerm> {
   let mut contents = String::new();
   file.read_to_string(&mut contents).unwrap();
 
-  let mod_root = parse_nodot(contents.as_str());
-  //println!("Parsed: {:?}", out_term)
-  process_module(mod_root);
+  match parse_nodot(contents.as_str()) {
+    Ok(t) => Some(t),
+    Err(e) => {
+      println!("aotc: {}:{}", filename, e);
+      None
+    },
+  }
+}
+
+
+fn read_etf(filename: &str, file: &mut File) -> Option<FTerm> {
+  let mut contents = Vec::new();
+  file.read_to_end(&mut contents).unwrap();
+
+  match etf::decode(&contents) {
+    Ok(t) => Some(t),
+    Err(e) => {
+      println!("aotc: {}: {:?}", filename, e);
+      None
+    },
+  }
 }
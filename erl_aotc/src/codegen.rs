@@ -1,8 +1,10 @@
+use erl_shared::atom::{Atom, AtomTable, AtomTooLong};
 use ll_types;
 
 use llvm::*;
 use llvm::core as ll;
 use std::ffi::CString;
+use std::os::raw::c_char;
 
 
 pub struct Codegen {
@@ -90,5 +92,37 @@ impl Codegen {
       ll::LLVMBuildRet(self.builder, val);
     }
   }
+
+
+  /// Add the module atom table as a constant byte array global `atom_table`
+  /// in the `AtomTable::to_bytes` format. The runtime reads it when loading
+  /// the module to map atom numbers in the code to its own atoms.
+  pub fn new_atom_table(&self,
+                        module: *mut LLVMModule,
+                        atoms: &AtomTable) -> Result<*mut LLVMValue, AtomTooLong> {
+    let bytes = atoms.to_bytes()?;
+    let name = CString::new("atom_table").unwrap();
+    unsafe {
+      let init = ll::LLVMConstStringInContext(
+        self.context,
+        bytes.as_ptr() as *const c_char,
+        bytes.len() as u32,
+        1, // do not add a terminating zero
+      );
+      let global = ll::LLVMAddGlobal(module, ll::LLVMTypeOf(init), name.as_ptr());
+      ll::LLVMSetInitializer(global, init);
+      ll::LLVMSetGlobalConstant(global, 1);
+      Ok(global)
+    }
+  }
+
+
+  /// Number of the atom in the module atom table as a constant, this is the
+  /// value of a `term_atom_t`.
+  pub fn const_atom(&self, atoms: &AtomTable, atom: Atom) -> Option<*mut LLVMValue> {
+    atoms.index_of(atom).map(|i| unsafe {
+      ll::LLVMConstInt(ll::LLVMInt64TypeInContext(self.context), i as u64, 0)
+    })
+  }
 }
 
@@ -2,6 +2,7 @@ use std::fmt;
 //use erl_shared::fterm::FTerm;
 
 #[derive(PartialOrd, PartialEq, Eq, Ord, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct MFA {
   pub m: String,
   pub f: String,
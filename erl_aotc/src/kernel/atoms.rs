@@ -0,0 +1,224 @@
+//! Collects atoms used by kernel code into the module atom table, in the order
+//! they appear in the code, so the same module always gets the same table.
+
+use erl_shared::atom::AtomTable;
+use erl_shared::fterm::FTerm;
+use kernel::*;
+
+
+impl Expr {
+  pub fn collect_atoms(&self, atoms: &mut AtomTable) {
+    match self {
+      Expr::Match(m) | Expr::GuardMatch(m) => m.collect_atoms(atoms),
+      Expr::Seq(s) => {
+        s.arg.collect_atoms(atoms);
+        s.body.collect_atoms(atoms);
+      },
+      Expr::Alt(a) => {
+        a.first.collect_atoms(atoms);
+        a.then.collect_atoms(atoms);
+      },
+      Expr::Enter(e) => {
+        e.op.collect_atoms(atoms);
+        collect_list(&e.args, atoms);
+      },
+      Expr::Return(r) => collect_list(&r.args, atoms),
+      Expr::Select(s) => {
+        s.var.collect_atoms(atoms);
+        for tc in &s.type_clauses {
+          for vc in &tc.values {
+            vc.val.collect_atoms(atoms);
+            vc.body.collect_atoms(atoms);
+          }
+        }
+      },
+      Expr::Guard(g) => {
+        for gc in &g.clauses {
+          gc.guard.collect_atoms(atoms);
+          gc.body.collect_atoms(atoms);
+        }
+      },
+      Expr::GuardBreak { args, .. } | Expr::Break { args, .. } =>
+        collect_list(args, atoms),
+      Expr::MultipleExprs(v) => collect_list(v, atoms),
+      Expr::Bif(c) | Expr::Call(c) => c.collect_atoms(atoms),
+      // The lifted function name is interned when its FunDef is added
+      Expr::MakeFun(mf) => {
+        collect_list(&mf.free, atoms);
+        collect_list(&mf.ret, atoms);
+      },
+      Expr::Try(t) | Expr::TryEnter(t) => {
+        t.arg.collect_atoms(atoms);
+        collect_list(&t.vars, atoms);
+        t.body.collect_atoms(atoms);
+        t.handler.collect_atoms(atoms);
+        collect_list(&t.ret, atoms);
+      },
+      Expr::Catch(c) => {
+        c.body.collect_atoms(atoms);
+        collect_list(&c.ret, atoms);
+      },
+      Expr::Receive(r) => {
+        r.var.collect_atoms(atoms);
+        r.body.collect_atoms(atoms);
+        r.timeout.collect_atoms(atoms);
+        r.action.collect_atoms(atoms);
+        collect_list(&r.ret, atoms);
+      },
+      Expr::ReceiveAccept { .. } | Expr::ReceiveNext { .. } => {},
+      Expr::Put { arg, ret, .. } | Expr::Protected { arg, ret, .. } => {
+        arg.collect_atoms(atoms);
+        ret.collect_atoms(atoms);
+      },
+      Expr::Test { op, args, .. } => {
+        op.collect_atoms(atoms);
+        collect_list(args, atoms);
+      },
+      Expr::Atom(a) => { atoms.add(*a); },
+      Expr::Int64(_) | Expr::Variable(_) | Expr::Nil => {},
+      Expr::Tuple { elements, .. } => collect_list(elements, atoms),
+      Expr::Value { val, .. } => collect_term_atoms(val, atoms),
+      Expr::Cons { hd, tl, .. } => {
+        hd.collect_atoms(atoms);
+        tl.collect_atoms(atoms);
+      },
+      Expr::ConstructBinary { segments, .. } => {
+        let mut seg = segments;
+        while let Some(s) = seg {
+          s.size.collect_atoms(atoms);
+          s.seg.collect_atoms(atoms);
+          seg = &s.next;
+        }
+      },
+      Expr::Map(m) => m.collect_atoms(atoms),
+    }
+  }
+}
+
+
+impl KMatch {
+  fn collect_atoms(&self, atoms: &mut AtomTable) {
+    collect_list(&self.vars, atoms);
+    self.body.collect_atoms(atoms);
+    collect_list(&self.ret, atoms);
+  }
+}
+
+
+impl KMap {
+  fn collect_atoms(&self, atoms: &mut AtomTable) {
+    self.var.collect_atoms(atoms);
+    for p in &self.pairs {
+      p.key.collect_atoms(atoms);
+      p.val.collect_atoms(atoms);
+    }
+  }
+}
+
+
+impl KCall {
+  fn collect_atoms(&self, atoms: &mut AtomTable) {
+    self.op.collect_atoms(atoms);
+    collect_list(&self.args, atoms);
+    collect_list(&self.ret, atoms);
+  }
+}
+
+
+impl Pattern {
+  fn collect_atoms(&self, atoms: &mut AtomTable) {
+    match self {
+      Pattern::Binary { segs, .. } => segs.collect_atoms(atoms),
+      Pattern::BinSeg(s) => {
+        s.size.collect_atoms(atoms);
+        s.seg.collect_atoms(atoms);
+      },
+      Pattern::BinInt(i) => i.size.collect_atoms(atoms),
+      Pattern::BinEnd { .. } => {},
+      Pattern::Map(m) => m.collect_atoms(atoms),
+      Pattern::Term(t) => collect_pattern_atoms(t, atoms),
+    }
+  }
+}
+
+
+impl FunRef {
+  fn collect_atoms(&self, atoms: &mut AtomTable) {
+    match self {
+      FunRef::MFArity { m, f, arity } => {
+        m.collect_atoms(atoms);
+        f.collect_atoms(atoms);
+        arity.collect_atoms(atoms);
+      },
+      FunRef::FArity { f, arity } => {
+        f.collect_atoms(atoms);
+        arity.collect_atoms(atoms);
+      },
+      FunRef::Bif(c) => c.collect_atoms(atoms),
+      // Local functions are called directly, their names are not needed
+      FunRef::Internal(_) => {},
+      FunRef::Var(v) => v.collect_atoms(atoms),
+    }
+  }
+}
+
+
+fn collect_list(exprs: &[Expr], atoms: &mut AtomTable) {
+  for e in exprs {
+    e.collect_atoms(atoms);
+  }
+}
+
+
+/// All atoms inside a literal value
+fn collect_term_atoms(t: &FTerm, atoms: &mut AtomTable) {
+  match t {
+    FTerm::Atom(a) => { atoms.add(*a); },
+    FTerm::List(v) | FTerm::Tuple(v) => {
+      for x in v {
+        collect_term_atoms(x, atoms);
+      }
+    },
+    FTerm::ImproperList(v, tail) => {
+      for x in v {
+        collect_term_atoms(x, atoms);
+      }
+      collect_term_atoms(tail, atoms);
+    },
+    FTerm::Map(pairs) => {
+      for (k, v) in pairs {
+        collect_term_atoms(k, atoms);
+        collect_term_atoms(v, atoms);
+      }
+    },
+    FTerm::ExportFun { module, fun, .. } => {
+      atoms.intern(module);
+      atoms.intern(fun);
+    },
+    FTerm::LocalFun { module, .. } => { atoms.intern(module); },
+    _ => {},
+  }
+}
+
+
+/// Value clause patterns are still kernel terms like `{k_atom, Anno, A}`,
+/// take atoms from values but not from the k_* tags and annotations
+fn collect_pattern_atoms(t: &FTerm, atoms: &mut AtomTable) {
+  if let Some(v) = t.as_list() {
+    for x in v {
+      collect_pattern_atoms(x, atoms);
+    }
+    return
+  }
+  match (t.tuple_tag(), t.as_tuple()) {
+    (Some("k_atom"), Some(v)) if v.len() == 3 => collect_term_atoms(&v[2], atoms),
+    (Some("k_literal"), Some(v)) if v.len() == 3 => collect_term_atoms(&v[2], atoms),
+    (Some("k_var"), _) => {},
+    (Some(_), Some(v)) => {
+      for x in v.iter().skip(2) {
+        collect_pattern_atoms(x, atoms);
+      }
+    },
+    _ => {},
+  }
+}
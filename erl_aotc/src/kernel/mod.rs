@@ -1,12 +1,17 @@
 use erl_types::MFA;
+use erl_shared::atom::{Atom, AtomTable};
 use erl_shared::fterm::FTerm;
 use std::collections::BTreeMap;
 
+pub mod atoms;
 pub mod expr;
 pub mod parse;
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize",
+           serde(tag = "type", content = "value", rename_all = "snake_case"))]
 pub enum Expr {
   // Logic and actions
   Match(Box<KMatch>),
@@ -16,59 +21,192 @@ pub enum Expr {
   Return(KReturn),
   Select(Box<KSelect>),
   Guard(KGuard),
-  GuardBreak { anno: FTerm, args: Vec<Expr> },
+  GuardBreak { anno: KAnno, args: Vec<Expr> },
+  /// Leave the enclosing k_match or k_alt, binding args to its ret variables
+  Break { anno: KAnno, args: Vec<Expr> },
   MultipleExprs(Vec<Expr>),
   Bif(Box<KCall>),
+  /// Closure creation, the `make_fun` internal bif
+  MakeFun(Box<KMakeFun>),
   Call(Box<KCall>),
-  Put { anno: FTerm, arg: Box<Expr>, ret: Box<Expr> },
-  Protected { anno: FTerm, arg: Box<Expr>, ret: Box<Expr> },
-  Test { anno: FTerm, op: Box<FunRef>, args: Vec<Expr>, inverted: bool },
+  Put { anno: KAnno, arg: Box<Expr>, ret: Box<Expr> },
+  Protected { anno: KAnno, arg: Box<Expr>, ret: Box<Expr> },
+  Test { anno: KAnno, op: Box<FunRef>, args: Vec<Expr>, inverted: bool },
   GuardMatch(Box<KMatch>),
+  Try(Box<KTry>),
+  /// Try in tail position, has no ret variables
+  TryEnter(Box<KTry>),
+  Catch(Box<KCatch>),
+  Receive(Box<KReceive>),
+  /// Remove the current message from the mailbox
+  ReceiveAccept { anno: KAnno },
+  /// Leave the current message in the mailbox and wait for the next one
+  ReceiveNext { anno: KAnno },
 
   // Values, literals, constructors and constants
-  Atom(String),
+  Atom(Atom),
   Int64(i64),
-  Variable(String),
+  /// Variable name, interned but not added to the module atom table
+  Variable(Atom),
   Nil,
-  Tuple { anno: FTerm, elements: Vec<Expr> },
-  Value { anno: FTerm, val: FTerm },
-  Cons { anno: FTerm, hd: Box<Expr>, tl: Box<Expr> },
+  Tuple { anno: KAnno, elements: Vec<Expr> },
+  Value { anno: KAnno, val: FTerm },
+  Cons { anno: KAnno, hd: Box<Expr>, tl: Box<Expr> },
   ConstructBinary {
-    anno: FTerm,
+    anno: KAnno,
     segments: Option<Box<KBinarySegment>>
   },
+  /// Map construction or update
+  Map(Box<KMap>),
 }
 
 
+/// {k_map, anno, var, op, pairs}: `var` is the map to update, the literal
+/// `#{}` when a new map is built
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KMap {
+  pub anno: KAnno,
+  pub var: Expr,
+  pub op: MapOp,
+  pub pairs: Vec<KMapPair>,
+}
+
+
+/// `K => V` may add a key, `K := V` requires the key to exist
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "snake_case"))]
+pub enum MapOp {
+  Assoc,
+  Exact,
+}
+
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KMapPair {
+  pub anno: KAnno,
+  pub key: Expr,
+  pub val: Expr,
+}
+
+
+/// Annotation of a kernel node. Most nodes carry `{k, Used, Defined, User}`,
+/// others only the user annotation list, then used and defined are empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KAnno {
+  /// Variables used by the node
+  pub used: Vec<Atom>,
+  /// Variables the node binds
+  pub defined: Vec<Atom>,
+  pub line: Option<u32>,
+  pub file: Option<String>,
+  /// Set on the body of a fun
+  pub fun_id: Option<FunId>,
+  /// Compiler flags like `compiler_generated` or `no_usage`
+  pub flags: Vec<Atom>,
+  /// User annotations not recognized above, such as `{function_name, FA}`
+  pub other: Vec<FTerm>,
+}
+
+
+/// `{id, {Index, Uniq, Name}}` identifying a fun
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct FunId {
+  pub index: u32,
+  pub uniq: u32,
+  pub name: Atom,
+}
+
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KBinarySegment {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub size: Expr,
   pub unit: u32,
-  pub seg_type: String, // TODO: type this
-  pub flags: Vec<String>, // TODO: type this
+  pub seg_type: BinSegType,
+  pub flags: Vec<BinFlag>,
   pub seg: Expr,
   pub next: Option<Box<KBinarySegment>>,
 }
 
 
+/// Type of a bit syntax segment, `bits` and `bytes` are `Binary` with a unit
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "snake_case"))]
+pub enum BinSegType {
+  Integer,
+  Float,
+  Binary,
+  Utf8,
+  Utf16,
+  Utf32,
+}
+
+
+/// Signedness and endianness of a bit syntax segment
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "snake_case"))]
+pub enum BinFlag {
+  Signed,
+  Unsigned,
+  Big,
+  Little,
+  Native,
+}
+
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize",
+           serde(tag = "type", content = "value", rename_all = "snake_case"))]
 pub enum FunRef {
   MFArity { m: Expr, f: Expr, arity: Expr },
   FArity { f: Expr, arity: Expr },
   Bif(Box<KCall>),
   Internal(MFA),
+  /// Call the fun value held in a variable
+  Var(Expr),
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KCall {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub op: FunRef,
   pub args: Vec<Expr>,
   pub ret: Vec<Expr>
 }
 
+/// {k_bif, anno, {k_internal, _, make_fun, N}, [Name, Arity | Free], ret}
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KMakeFun {
+  pub anno: KAnno,
+  /// Lifted function, takes the closure arguments followed by the free vars
+  pub fun: MFA,
+  pub free: Vec<Expr>,
+  /// Index and uniq of the fun, taken from the annotation
+  pub id: Option<FunId>,
+  pub ret: Vec<Expr>,
+}
+
+impl KMakeFun {
+  /// Arity of the closure as seen by its callers, `None` if the lifted
+  /// function takes fewer arguments than there are free variables
+  pub fn arity(&self) -> Option<usize> {
+    self.fun.a.checked_sub(self.free.len())
+  }
+}
+
+
 impl FunRef {
 //  pub fn get_mfa(&self) -> MFA {
 //    match self {
@@ -82,105 +220,218 @@ impl FunRef {
 
 /// Kernel Erlang k_match struct
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KMatch {
-  pub anno: FTerm,
+  pub anno: KAnno,
+  pub vars: Vec<Expr>,
+  pub body: Box<Expr>,
+  pub ret: Vec<Expr>,
+}
+
+
+/// `try Arg of Vars -> Body catch Class:Reason:Stacktrace -> Handler`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KTry {
+  pub anno: KAnno,
+  /// Protected expression
+  pub arg: Box<Expr>,
+  /// Bound to the results of arg on success
   pub vars: Vec<Expr>,
   pub body: Box<Expr>,
-  pub ret: Expr,
+  /// Exception variables
+  pub class: Expr,
+  pub reason: Expr,
+  pub stacktrace: Expr,
+  pub handler: Box<Expr>,
+  pub ret: Vec<Expr>,
 }
 
 
+/// Receive loop: each message is bound to var and matched by body, which
+/// ends with k_receive_accept or k_receive_next
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KReceive {
+  pub anno: KAnno,
+  pub var: Expr,
+  pub body: Box<Expr>,
+  /// Timeout in milliseconds or the atom `infinity`
+  pub timeout: Expr,
+  /// Body of `after`, runs on timeout
+  pub action: Box<Expr>,
+  pub ret: Vec<Expr>,
+}
+
+
+/// `catch Body`, ret is bound to the result or to the caught exception
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KCatch {
+  pub anno: KAnno,
+  pub body: Expr,
+  pub ret: Vec<Expr>,
+}
+
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KAlt {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub first: Box<Expr>,
   pub then: Box<Expr>,
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KEnter {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub op: FunRef,
   pub args: Vec<Expr>,
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KReturn {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub args: Vec<Expr>,
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KSelect {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub var: Expr,
   pub type_clauses: Vec<KTypeClause>,
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KTypeClause {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub type_: FTerm, // Atom
   pub values: Vec<KValClause>,
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KValClause {
-  pub anno: FTerm,
-  pub val: FTerm,
+  pub anno: KAnno,
+  pub val: Pattern,
   pub body: Expr,
 }
 
 
+/// Value matched by a k_val_clause. Binaries are matched one segment at a
+/// time, each segment clause binds the rest of the binary for the next select.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize",
+           serde(tag = "type", content = "value", rename_all = "snake_case"))]
+pub enum Pattern {
+  /// The value is a binary, `segs` is bound to the match state
+  Binary { anno: KAnno, segs: Expr },
+  BinSeg(Box<KBinSegPattern>),
+  BinInt(Box<KBinIntPattern>),
+  /// Nothing is left of the binary
+  BinEnd { anno: KAnno },
+  /// The value is a map which has the keys, values are bound to variables
+  Map(Box<KMap>),
+  /// Other patterns, as kernel terms
+  Term(FTerm),
+}
+
+
+/// Segment bound to a variable: {k_bin_seg, anno, size, unit, type, flags, seg, next}
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KBinSegPattern {
+  pub anno: KAnno,
+  pub size: Expr,
+  pub unit: u32,
+  pub seg_type: BinSegType,
+  pub flags: Vec<BinFlag>,
+  pub seg: Expr,
+  /// Rest of the binary, `None` if not used
+  pub next: Option<Expr>,
+}
+
+
+/// Integer segment with a known value: {k_bin_int, anno, size, unit, flags, val, next}
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct KBinIntPattern {
+  pub anno: KAnno,
+  pub size: Expr,
+  pub unit: u32,
+  pub flags: Vec<BinFlag>,
+  /// Integer value, may be a bignum when several segments were combined
+  pub val: FTerm,
+  pub next: Option<Expr>,
+}
+
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KSeq {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub arg: Expr,
   pub body: Expr,
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KGuardClause {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub guard: Expr,
   pub body: Expr,
 }
 
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct KGuard {
-  pub anno: FTerm,
+  pub anno: KAnno,
   pub clauses: Vec<KGuardClause>,
 }
 
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct FunDef {
   pub funarity: MFA,
+  /// Set when the function is the lifted body of a fun
+  pub fun_id: Option<FunId>,
   k_code: Expr, // Kernel Code (parsed from Kernel Eterm input)
 }
 
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Module {
   name: String,
   imports: Vec<MFA>,
   exports: Vec<MFA>,
   attrs: FTerm,
-  funs: BTreeMap<MFA, FunDef>
+  #[cfg_attr(feature = "serialize", serde(with = "fun_list"))]
+  funs: BTreeMap<MFA, FunDef>,
+  /// Atoms used by the code, the module name is always number 0
+  pub atoms: AtomTable,
 }
 
 
 impl FunDef {
-  pub fn new(name: String, arity: usize, k_code: Expr) -> FunDef {
+  pub fn new(name: String, arity: usize, fun_id: Option<FunId>,
+             k_code: Expr) -> FunDef {
     FunDef {
       funarity: MFA::new2(name, arity),
+      fun_id,
       k_code
     }
   }
@@ -192,18 +443,54 @@ impl Module {
              imports: Vec<MFA>,
              exports: Vec<MFA>,
              attrs: FTerm) -> Module {
+    let mut atoms = AtomTable::new();
+    atoms.intern(&name);
     Module {
       name,
       imports,
       exports,
       attrs,
       funs: BTreeMap::new(),
+      atoms,
     }
   }
 
 
+  /// Add the function, and atoms used by it to the atom table.
   pub fn add_fun(&mut self, fdef: FunDef) {
     let fa = fdef.funarity.clone();
+    self.atoms.intern(&fa.f);
+    fdef.k_code.collect_atoms(&mut self.atoms);
     self.funs.insert(fa, fdef);
   }
+
+
+  /// Find the lifted function which a closure will call.
+  pub fn lifted_fun(&self, mf: &KMakeFun) -> Option<&FunDef> {
+    self.funs.get(&mf.fun)
+  }
+}
+
+
+/// Functions are written as a list, the key is in each `FunDef`
+#[cfg(feature = "serialize")]
+mod fun_list {
+  use erl_types::MFA;
+  use kernel::FunDef;
+  use serde::de::{Deserialize, Deserializer};
+  use serde::ser::Serializer;
+  use std::collections::BTreeMap;
+
+
+  pub fn serialize<S: Serializer>(funs: &BTreeMap<MFA, FunDef>,
+                                  s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(funs.values())
+  }
+
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(d: D)
+    -> Result<BTreeMap<MFA, FunDef>, D::Error> {
+    let funs = Vec::<FunDef>::deserialize(d)?;
+    Ok(funs.into_iter().map(|f| (f.funarity.clone(), f)).collect())
+  }
 }
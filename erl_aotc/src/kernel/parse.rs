@@ -1,103 +1,105 @@
 /// Parses Kernel Erlang input as Erlang Term in text.
 /// Outputs a similar kernel::Kerl structure
 
-use erl_shared::fterm::FTerm;
+use erl_shared::atom::Atom;
+use erl_shared::fterm::{FTerm, TermError};
 use erl_types::MFA;
 use kernel::*;
 
 
+/// Print the kernel tree as it is parsed, only with the `trace` feature
+macro_rules! trace {
+  ($($arg:tt)*) => {
+    if cfg!(feature = "trace") {
+      println!($($arg)*)
+    }
+  };
+}
+
+
+/// Errors are reported as the part of the input which has unexpected shape
+pub type ParseResult<T> = Result<T, TermError>;
+
+
 /// Compile a parsed Kernel Module erlang term
-pub fn process_module(mroot: FTerm) -> Module {
+pub fn process_module(mroot: &FTerm) -> ParseResult<Module> {
   // Step 1: Unwrap tuple with module elements and parse imports/exports
-  let (mut mod1, fdefs) = match mroot {
-    FTerm::Tuple(mdef) => create_kmod(mdef),
-    _ => panic!("Expected: kernel module (k_mdef)")
-  };
-  println!("{:?}", mod1);
+  let (mod1, fdefs) = create_kmod(mroot)?;
+  trace!("{:?}", mod1);
   // Step 2: Parse function definitions
   process_kmod_fdefs(mod1, fdefs)
 }
 
 
 /// Unwrap tuple with module elements and parse imports/exports
-pub fn create_kmod(mdef: Vec<FTerm>) -> (Module, FTerm) {
+pub fn create_kmod(mroot: &FTerm) -> ParseResult<(Module, &FTerm)> {
+  // {k_mdef, anno, name, exports, attributes, body}
+  let mdef = mroot.expect_tagged_tuple("k_mdef", 6)?;
   let m_imports = &mdef[1];
   let m_name = &mdef[2];
   let m_exports = &mdef[3];
   let m_attrs = &mdef[4];
   let m_fdefs = &mdef[5];
 
-  let m = Module::new(m_name.get_atom_text(),
-                      parse_mfa_list(m_imports),
-                      parse_mfa_list(m_exports),
+  let m = Module::new(m_name.expect_atom()?.to_string(),
+                      parse_mfa_list(m_imports)?,
+                      parse_mfa_list(m_exports)?,
                       m_attrs.clone());
-  (m, m_fdefs.clone())
+  Ok((m, m_fdefs))
 }
 
 
 /// Input: A list of m:f/a or f/a:
 /// `FTerm::List[FTerm::Tuple{FTerm::Atom(fun),Fterm::I64(arity)}, ...]`
 /// Returns: vector of MFA structs
-fn parse_mfa_list(lst: &FTerm) -> Vec<MFA> {
+fn parse_mfa_list(lst: &FTerm) -> ParseResult<Vec<MFA>> {
   let mut outp = Vec::<MFA>::new();
-  match lst {
-    FTerm::List(vec) => {
-      // For each pair or triple in list...
-      for listeach in vec {
-        if let FTerm::Tuple(tvec) = listeach {
-          match tvec.len() {
-            2 => outp.push(MFA::new2(tvec[0].get_atom_text(),
-                                     tvec[1].get_i64() as usize)),
-            3 => outp.push(MFA::new3(tvec[0].get_atom_text(),
-                                     tvec[1].get_atom_text(),
-                                     tvec[2].get_i64() as usize)),
-            _ =>
-              panic!("FTerm::Tuple of 2 or 3 is expected")
-          }
-        } else {
-          panic!("FTerm::Tuple of 2 or 3 is expected, got {}", lst)
-        }
-      }
-      return outp;
+  // For each pair or triple in list...
+  for listeach in lst.expect_list()? {
+    let tvec = listeach.expect_tuple()?;
+    match tvec.len() {
+      2 => outp.push(MFA::new2(tvec[0].expect_atom()?.to_string(),
+                               tvec[1].expect_i64()? as usize)),
+      3 => outp.push(MFA::new3(tvec[0].expect_atom()?.to_string(),
+                               tvec[1].expect_atom()?.to_string(),
+                               tvec[2].expect_i64()? as usize)),
+      _ => return Err(TermError::new("{f, arity} or {m, f, arity}", listeach)),
     }
-    FTerm::EmptyList => {
-      return outp;
-    }
-    _ => { panic!("FTerm::List is expected, got {}", lst) }
-  };
+  }
+  Ok(outp)
 }
 
 
 /// Parse function definitions, return updated Kernel Module.
-fn process_kmod_fdefs(mut kmod: Module, fdefs: FTerm) -> Module {
-  for fdef in fdefs.into_iter() {
+fn process_kmod_fdefs(mut kmod: Module, fdefs: &FTerm) -> ParseResult<Module> {
+  for fdef in fdefs.expect_list()? {
     // {k_fdef, anno, func, arity, attrs, body}
-    let kfun = process_fun(&mut kmod, fdef.get_vec());
+    let kfun = process_fun(&mut kmod, fdef)?;
     kmod.add_fun(kfun)
   }
-  kmod
+  Ok(kmod)
 }
 
 
-fn process_fun(_kmod: &mut Module,
-               fdef_vec: Vec<FTerm>) -> FunDef
-{
-  assert!(fdef_vec[0].is_atom_of("k_fdef"), "Expected 'f_def', got {:?}", fdef_vec);
+fn process_fun(_kmod: &mut Module, fdef: &FTerm) -> ParseResult<FunDef> {
+  let fdef_vec = fdef.expect_tagged_tuple("k_fdef", 6)?;
+  let fanno = parse_anno(&fdef_vec[1])?;
   let fname = &fdef_vec[2];
   let farity = &fdef_vec[3];
   let fattrs = &fdef_vec[4];
   let fbody = &fdef_vec[5];
 
-  println!("------ {}/{} ------", fname, farity);
-  println!("fn attrs={} {{", fattrs);
+  trace!("------ {}/{} ------", fname, farity);
+  trace!("fn attrs={} {{", fattrs);
 
-  let k_code = parse_expr(0, &fbody);
+  let k_code = parse_expr(0, &fbody)?;
 
-  println!("}}");
+  trace!("}}");
 
-  FunDef::new(fname.get_atom_text(),
-              farity.get_i64() as usize,
-              k_code)
+  Ok(FunDef::new(fname.expect_atom()?.to_string(),
+                 farity.expect_i64()? as usize,
+                 fanno.fun_id,
+                 k_code))
 }
 
 
@@ -138,423 +140,1170 @@ fn ii(indent: u32) -> String {
 //}
 
 
-fn parse_match(indent: u32, kmatch: &FTerm) -> Expr {
+fn parse_match(indent: u32, kmatch: &FTerm) -> ParseResult<Expr> {
   // {k_match, anno, vars, body, ret}
-  let match_vec = kmatch.get_tuple_vec();
-  assert!(match_vec[0].is_atom_of("k_match"));
+  let match_vec = kmatch.expect_tagged_tuple("k_match", 5)?;
 
   let vars = &match_vec[2];
   let body = &match_vec[3];
   let ret = &match_vec[4];
-  println!("{}k_match {} -> ret {} {{", ii(indent), vars, ret);
-  let body = Box::new(parse_expr(indent + 1, body));
-  println!("{}}} % end match", ii(indent));
+  trace!("{}k_match {} -> ret {} {{", ii(indent), vars, ret);
+  let body = Box::new(parse_expr(indent + 1, body)?);
+  trace!("{}}} % end match", ii(indent));
 
   let km = KMatch {
-    anno: match_vec[1].clone(),
-    vars: parse_expr_list(indent, vars),
+    anno: parse_anno(&match_vec[1])?,
+    vars: parse_expr_list(indent, vars)?,
     body,
-    ret: parse_ret(ret),
+    ret: parse_ret(indent, ret)?,
   };
-  Expr::Match(Box::new(km))
+  Ok(Expr::Match(Box::new(km)))
 }
 
 
-fn parse_expr_list(indent: u32, vars: &FTerm) -> Vec<Expr> {
-  if !vars.is_list() {
-    panic!("List of something is expected, found {}", vars)
+/// Elements of a list, `~p` prints lists of small integers as strings
+fn term_list(t: &FTerm) -> ParseResult<Vec<FTerm>> {
+  match t {
+    FTerm::String(s) => Ok(s.chars().map(|c| FTerm::Int64(c as i64)).collect()),
+    _ => Ok(t.expect_list()?.to_vec()),
   }
-  let mut result = Vec::<Expr>::new();
-  for v in vars.get_list_vec() {
-    result.push(_parse_expr_2(indent, &v))
+}
+
+
+fn parse_anno(anno: &FTerm) -> ParseResult<KAnno> {
+  // {k, used, defined, user_anno} or just user_anno
+  if let Some(k) = anno.as_tagged_tuple("k", 4) {
+    let mut result = parse_user_anno(&k[3])?;
+    result.used = parse_var_names(&k[1])?;
+    result.defined = parse_var_names(&k[2])?;
+    return Ok(result)
   }
-  result
+  parse_user_anno(anno)
 }
 
 
-fn parse_expr(indent: u32, expr: &FTerm) -> Expr {
+fn parse_var_names(names: &FTerm) -> ParseResult<Vec<Atom>> {
+  term_list(names)?.iter().map(|n| match n {
+    FTerm::Atom(a) => Ok(*a),
+    FTerm::Int64(i) => Ok(Atom::new(&i.to_string())),
+    other => Err(TermError::new("variable name", other)),
+  }).collect()
+}
+
+
+fn parse_user_anno(anno: &FTerm) -> ParseResult<KAnno> {
+  let mut result = KAnno::default();
+  for a in term_list(anno)? {
+    match (&a, a.tuple_tag()) {
+      (FTerm::Int64(_), _) => result.line = Some(expect_u32(&a, "line number")?),
+      (FTerm::Atom(flag), _) => result.flags.push(*flag),
+      // {location, {Line, Column}} in newer compilers
+      (_, Some("location")) => {
+        let loc = a.expect_tagged_tuple("location", 2)?;
+        let line = match loc[1].as_tuple() {
+          Some(lc) if !lc.is_empty() => &lc[0],
+          _ => &loc[1],
+        };
+        result.line = Some(expect_u32(line, "line number")?);
+      },
+      (_, Some("file")) => {
+        let file = a.expect_tagged_tuple("file", 2)?;
+        let name: String = match &file[1] {
+          FTerm::String(s) => s.clone(),
+          other => term_list(other)?.iter()
+            .map(|c| c.as_i64().and_then(|c| ::std::char::from_u32(c as u32)))
+            .collect::<Option<String>>()
+            .ok_or_else(|| TermError::new("file name", other))?,
+        };
+        result.file = Some(name);
+      },
+      (_, Some("id")) => {
+        let id = a.expect_tagged_tuple("id", 2)?;
+        let id_vec = id[1].expect_tuple()?;
+        if id_vec.len() != 3 {
+          return Err(TermError::new("fun id {Index, Uniq, Name}", &id[1]))
+        }
+        result.fun_id = Some(FunId {
+          index: expect_u32(&id_vec[0], "fun index")?,
+          uniq: expect_u32(&id_vec[1], "fun uniq")?,
+          name: Atom::new(id_vec[2].expect_atom()?),
+        });
+      },
+      _ => result.other.push(a.clone()),
+    }
+  }
+  Ok(result)
+}
+
+
+/// Non-negative integer which fits into u32
+fn expect_u32(t: &FTerm, what: &str) -> ParseResult<u32> {
+  match t.as_i64() {
+    Some(i) if i >= 0 && i <= u32::max_value() as i64 => Ok(i as u32),
+    _ => Err(TermError::new(what, t)),
+  }
+}
+
+
+fn parse_expr_list(indent: u32, vars: &FTerm) -> ParseResult<Vec<Expr>> {
+  let vars_vec = vars.expect_list()?;
+  let mut result = Vec::<Expr>::with_capacity(vars_vec.len());
+  for v in vars_vec {
+    result.push(_parse_expr_2(indent, v)?)
+  }
+  Ok(result)
+}
+
+
+fn parse_expr(indent: u32, expr: &FTerm) -> ParseResult<Expr> {
   if expr.is_int() {
-    return Expr::Int64(expr.get_i64())
-  } else if expr.is_atom() {
-    return Expr::Atom(expr.get_atom_text())
+    return Ok(Expr::Int64(expr.expect_i64()?))
+  } else if let FTerm::Atom(a) = expr {
+    return Ok(Expr::Atom(*a))
   } else if expr.is_list() {
-    return Expr::MultipleExprs(parse_expr_list(indent, expr))
+    return Ok(Expr::MultipleExprs(parse_expr_list(indent, expr)?))
   }
-  return _parse_expr_2(indent, expr)
+  _parse_expr_2(indent, expr)
 }
 
 
-fn _parse_expr_2(indent: u32, expr: &FTerm) -> Expr {
+fn _parse_expr_2(indent: u32, expr: &FTerm) -> ParseResult<Expr> {
   // So val is a tuple, parse it as a k_* tuple or something
-  let val_vec = expr.get_tuple_vec();
-  match val_vec[0].get_atom_text().as_ref() {
+  let tag = match expr.tuple_tag() {
+    Some(t) => t,
+    None => return Err(TermError::new("kernel expression {k_..., ...}", expr)),
+  };
+  let e = match tag {
     "k_var" => {
       // {k_var, anno, name}
-      assert!(val_vec[0].is_atom_of("k_var"));
+      let val_vec = expr.expect_tagged_tuple("k_var", 3)?;
       match &val_vec[2] {
-        FTerm::Atom(s) => Expr::Variable(s.to_string()),
-        FTerm::Int64(i) => Expr::Variable(i.to_string()),
-        _ => panic!("Don't know how to parse val {}", val_vec[2]),
+        FTerm::Atom(s) => Expr::Variable(*s),
+        FTerm::Int64(i) => Expr::Variable(Atom::new(&i.to_string())),
+        other => return Err(TermError::new("variable name", other)),
+      }
+    },
+    "k_bif" => {
+      let op = &expr.expect_tagged_tuple("k_bif", 5)?[2];
+      if op.tuple_tag() == Some("k_internal")
+         && parse_kinternal(op)?.f == "make_fun" {
+        Expr::MakeFun(Box::new(parse_make_fun(indent, expr)?))
+      } else {
+        Expr::Bif(Box::new(parse_kcall(indent, expr)?))
       }
     },
-    "k_bif" => Expr::Bif(Box::new(parse_kcall(indent, val_vec))),
     "k_atom" => { // {k_atom, anno, val}
-      Expr::Atom(val_vec[2].get_atom_text())
+      let val_vec = expr.expect_tagged_tuple("k_atom", 3)?;
+      match &val_vec[2] {
+        FTerm::Atom(a) => Expr::Atom(*a),
+        other => return Err(TermError::new("atom", other)),
+      }
     },
     "k_int" => { // {k_int, anno, val}
-      Expr::Int64(val_vec[2].get_i64())
+      let val_vec = expr.expect_tagged_tuple("k_int", 3)?;
+      Expr::Int64(val_vec[2].expect_i64()?)
     },
     "k_binary" => { // {k_binary, anno, segs}
+      let val_vec = expr.expect_tagged_tuple("k_binary", 3)?;
       Expr::ConstructBinary {
-        anno: val_vec[1].clone(),
-        segments: parse_binary_segments(indent, &val_vec[2]),
+        anno: parse_anno(&val_vec[1])?,
+        segments: parse_binary_segments(indent, &val_vec[2])?,
       }
     },
-    "k_call" => Expr::Call(Box::new(parse_kcall(indent, val_vec))),
-    "k_literal" => // {k_literal, anno, val}
+    "k_call" => Expr::Call(Box::new(parse_kcall(indent, expr)?)),
+    "k_map" => Expr::Map(Box::new(parse_map(indent, expr)?)),
+    "k_literal" => { // {k_literal, anno, val}
+      let val_vec = expr.expect_tagged_tuple("k_literal", 3)?;
       Expr::Value {
-        anno: val_vec[1].clone(),
+        anno: parse_anno(&val_vec[1])?,
         val: val_vec[2].clone(),
-      },
-    "k_put" => {
+      }
+    },
+    "k_put" => { // {k_put, anno, arg, ret}
+      let val_vec = expr.expect_tagged_tuple("k_put", 4)?;
       Expr::Put {
-        anno: val_vec[1].clone(),
-        arg: Box::new(parse_expr(indent, &val_vec[2])),
-        ret: Box::new(parse_expr(indent, &val_vec[3])),
+        anno: parse_anno(&val_vec[1])?,
+        arg: Box::new(parse_expr(indent, &val_vec[2])?),
+        ret: Box::new(parse_expr(indent, &val_vec[3])?),
       }
     },
-    "k_cons" => {
+    "k_cons" => { // {k_cons, anno, hd, tl}
+      let val_vec = expr.expect_tagged_tuple("k_cons", 4)?;
       Expr::Cons {
-        anno: val_vec[1].clone(),
-        hd: Box::new(parse_expr(indent, &val_vec[2])),
-        tl: Box::new(parse_expr(indent, &val_vec[3])),
+        anno: parse_anno(&val_vec[1])?,
+        hd: Box::new(parse_expr(indent, &val_vec[2])?),
+        tl: Box::new(parse_expr(indent, &val_vec[3])?),
       }
     },
     "k_nil" => Expr::Nil,
     "k_protected" => { // {k_protected, anno, arg, ret}
+      let val_vec = expr.expect_tagged_tuple("k_protected", 4)?;
       Expr::Protected {
-        anno: val_vec[1].clone(),
-        arg: Box::new(parse_expr(indent, &val_vec[2])),
-        ret: Box::new(parse_expr(indent, &val_vec[3])),
+        anno: parse_anno(&val_vec[1])?,
+        arg: Box::new(parse_expr(indent, &val_vec[2])?),
+        ret: Box::new(parse_expr(indent, &val_vec[3])?),
       }
     },
-    "k_test" => {
+    "k_test" => { // {k_test, anno, op, args, inverted}
+      let val_vec = expr.expect_tagged_tuple("k_test", 5)?;
       Expr::Test {
-        anno: val_vec[1].clone(),
-        op: Box::new(parse_funref(indent, &val_vec[2])),
-        args: parse_expr_list(indent, &val_vec[3]),
-        inverted: val_vec[4].get_bool(),
+        anno: parse_anno(&val_vec[1])?,
+        op: Box::new(parse_funref(indent, &val_vec[2])?),
+        args: parse_expr_list(indent, &val_vec[3])?,
+        inverted: val_vec[4].expect_bool()?,
       }
     },
     "k_guard_match" => { // {k_guard_match, anno, vars, body, ret}
+      let val_vec = expr.expect_tagged_tuple("k_guard_match", 5)?;
       let km = Box::new(KMatch {
-        anno: val_vec[1].clone(),
-        vars: parse_expr_list(indent, &val_vec[2]),
-        body: Box::new(parse_expr(indent+1, &val_vec[3])),
-        ret: parse_expr(indent, &val_vec[4]),
+        anno: parse_anno(&val_vec[1])?,
+        vars: parse_expr_list(indent, &val_vec[2])?,
+        body: Box::new(parse_expr(indent+1, &val_vec[3])?),
+        ret: parse_ret(indent, &val_vec[4])?,
       });
       Expr::GuardMatch(km)
     },
     "k_tuple" => { // {k_tuple, anno, elements}
+      let val_vec = expr.expect_tagged_tuple("k_tuple", 3)?;
       Expr::Tuple {
-        anno: val_vec[1].clone(),
-        elements: parse_expr_list(indent, &val_vec[2]),
+        anno: parse_anno(&val_vec[1])?,
+        elements: parse_expr_list(indent, &val_vec[2])?,
       }
     },
-    "k_match" => parse_match(indent + 1, &expr),
-    "k_seq" => parse_seq(indent + 1, &expr),
-    "k_alt" => parse_alt(indent + 1, &expr),
-    "k_enter" => parse_enter(indent + 1, &expr),
-    "k_return" => parse_return(indent + 1, &expr),
-    "k_select" => parse_select(indent + 1, &expr),
-    "k_guard" => parse_guard(indent + 1, &expr),
-    "k_guard_break" => parse_kguard_break(indent + 1, &expr),
-
-    // TODO: k_enter
-    // TODO: k_try, k_try_enter
-    // TODO: k_catch
-    // TODO: k_receive, k_receive_accept, k_receive_next
-    // TODO: k_break
-
-    _other => panic!("_parse_expr_2 doesn't know how to handle {} in {:?}",
-                     val_vec[0], val_vec)
-  }
+    "k_match" => parse_match(indent + 1, &expr)?,
+    "k_seq" => parse_seq(indent + 1, &expr)?,
+    "k_alt" => parse_alt(indent + 1, &expr)?,
+    "k_enter" => parse_enter(indent + 1, &expr)?,
+    "k_return" => parse_return(indent + 1, &expr)?,
+    "k_select" => parse_select(indent + 1, &expr)?,
+    "k_guard" => parse_guard(indent + 1, &expr)?,
+    "k_guard_break" => parse_kguard_break(indent + 1, &expr)?,
+    "k_break" => parse_break(indent + 1, &expr)?,
+    "k_try" => Expr::Try(Box::new(parse_try(indent + 1, &expr, "k_try", 8)?)),
+    "k_try_enter" =>
+      Expr::TryEnter(Box::new(parse_try(indent + 1, &expr, "k_try_enter", 7)?)),
+    "k_catch" => parse_catch(indent + 1, &expr)?,
+    "k_receive" => parse_receive(indent + 1, &expr)?,
+    "k_receive_accept" => { // {k_receive_accept, anno}
+      let val_vec = expr.expect_tagged_tuple("k_receive_accept", 2)?;
+      trace!("{}k_receive_accept", ii(indent));
+      Expr::ReceiveAccept { anno: parse_anno(&val_vec[1])? }
+    },
+    "k_receive_next" => { // {k_receive_next, anno}
+      let val_vec = expr.expect_tagged_tuple("k_receive_next", 2)?;
+      trace!("{}k_receive_next", ii(indent));
+      Expr::ReceiveNext { anno: parse_anno(&val_vec[1])? }
+    },
+    _other => return Err(TermError::new("known kernel expression", expr)),
+  };
+  Ok(e)
 }
 
 
-fn parse_binary_segments(indent: u32, seg: &FTerm) -> Option<Box<KBinarySegment>> {
+fn parse_binary_segments(indent: u32, seg: &FTerm)
+                         -> ParseResult<Option<Box<KBinarySegment>>> {
   // {k_bin_seg, anno = [], size, unit, type ,flags, seg, next}
-  let seg_vec = seg.get_tuple_vec();
-  if seg_vec[0].is_atom_of("k_bin_end") {
-    return None
+  if seg.as_tagged_tuple("k_bin_end", 2).is_some() {
+    return Ok(None)
   }
-  assert!(seg_vec[0].is_atom_of("k_bin_seg"),
-          "Expected {{k_bin_seg,...}} got {}", seg);
+  let seg_vec = seg.expect_tagged_tuple("k_bin_seg", 8)?;
 
   let bseg = KBinarySegment {
-    anno: seg_vec[1].clone(),
-    size: parse_expr(indent, &seg_vec[2]),
-    unit: seg_vec[3].get_i64() as u32,
-    seg_type: seg_vec[4].get_atom_text(),
-    flags: Vec::new(),
-    seg: parse_expr(indent, &seg_vec[6]),
-    next: parse_binary_segments(indent, &seg_vec[7]),
+    anno: parse_anno(&seg_vec[1])?,
+    size: parse_expr(indent, &seg_vec[2])?,
+    unit: seg_vec[3].expect_i64()? as u32,
+    seg_type: parse_bin_type(&seg_vec[4])?,
+    flags: parse_bin_flags(&seg_vec[5])?,
+    seg: parse_expr(indent, &seg_vec[6])?,
+    next: parse_binary_segments(indent, &seg_vec[7])?,
   };
   // Success
-  Some(Box::new(bseg))
+  Ok(Some(Box::new(bseg)))
+}
+
+
+fn parse_map(indent: u32, kmap: &FTerm) -> ParseResult<KMap> {
+  // {k_map, anno, var, op, pairs}
+  let map_vec = kmap.expect_tagged_tuple("k_map", 5)?;
+
+  let op = match map_vec[3].as_atom() {
+    Some("assoc") => MapOp::Assoc,
+    Some("exact") => MapOp::Exact,
+    _ => return Err(TermError::new("map operation assoc or exact", &map_vec[3])),
+  };
+  let mut pairs = Vec::new();
+  for p in map_vec[4].expect_list()? {
+    // {k_map_pair, anno, key, val}
+    let p_vec = p.expect_tagged_tuple("k_map_pair", 4)?;
+    pairs.push(KMapPair {
+      anno: parse_anno(&p_vec[1])?,
+      key: parse_expr(indent, &p_vec[2])?,
+      val: parse_expr(indent, &p_vec[3])?,
+    });
+  }
+  Ok(KMap {
+    anno: parse_anno(&map_vec[1])?,
+    var: parse_expr(indent, &map_vec[2])?,
+    op,
+    pairs,
+  })
+}
+
+
+fn parse_bin_type(t: &FTerm) -> ParseResult<BinSegType> {
+  match t.as_atom() {
+    Some("integer") => Ok(BinSegType::Integer),
+    Some("float") => Ok(BinSegType::Float),
+    Some("binary") => Ok(BinSegType::Binary),
+    Some("utf8") => Ok(BinSegType::Utf8),
+    Some("utf16") => Ok(BinSegType::Utf16),
+    Some("utf32") => Ok(BinSegType::Utf32),
+    _ => Err(TermError::new("bit syntax segment type", t)),
+  }
+}
+
+
+fn parse_bin_flags(flags: &FTerm) -> ParseResult<Vec<BinFlag>> {
+  let mut result = Vec::new();
+  for f in flags.expect_list()? {
+    let flag = match f.as_atom() {
+      Some("signed") => BinFlag::Signed,
+      Some("unsigned") => BinFlag::Unsigned,
+      Some("big") => BinFlag::Big,
+      Some("little") => BinFlag::Little,
+      Some("native") => BinFlag::Native,
+      _ => return Err(TermError::new("bit syntax segment flag", f)),
+    };
+    result.push(flag);
+  }
+  Ok(result)
 }
 
 
-fn parse_ret(ret: &FTerm) -> Expr {
-  match ret {
-    FTerm::EmptyList => Expr::Nil,
-    _other => panic!("TODO parse_ret for {}", ret),
+/// Rest of a matched binary, `[]` when the match does not need it
+fn parse_bin_next(indent: u32, next: &FTerm) -> ParseResult<Option<Expr>> {
+  match next {
+    FTerm::EmptyList => Ok(None),
+    _ => Ok(Some(parse_expr(indent, next)?)),
   }
 }
 
 
-fn parse_seq(indent: u32, kseq: &FTerm) -> Expr {
+fn parse_pattern(indent: u32, pat: &FTerm) -> ParseResult<Pattern> {
+  let p = match pat.tuple_tag() {
+    Some("k_binary") => { // {k_binary, anno, segs}
+      let p_vec = pat.expect_tagged_tuple("k_binary", 3)?;
+      Pattern::Binary {
+        anno: parse_anno(&p_vec[1])?,
+        segs: parse_expr(indent, &p_vec[2])?,
+      }
+    },
+    Some("k_bin_seg") => {
+      // {k_bin_seg, anno, size, unit, type, flags, seg, next}
+      let p_vec = pat.expect_tagged_tuple("k_bin_seg", 8)?;
+      Pattern::BinSeg(Box::new(KBinSegPattern {
+        anno: parse_anno(&p_vec[1])?,
+        size: parse_expr(indent, &p_vec[2])?,
+        unit: p_vec[3].expect_i64()? as u32,
+        seg_type: parse_bin_type(&p_vec[4])?,
+        flags: parse_bin_flags(&p_vec[5])?,
+        seg: parse_expr(indent, &p_vec[6])?,
+        next: parse_bin_next(indent, &p_vec[7])?,
+      }))
+    },
+    Some("k_bin_int") => {
+      // {k_bin_int, anno, size, unit, flags, val, next}
+      let p_vec = pat.expect_tagged_tuple("k_bin_int", 7)?;
+      if !p_vec[5].is_int() {
+        return Err(TermError::new("integer", &p_vec[5]))
+      }
+      Pattern::BinInt(Box::new(KBinIntPattern {
+        anno: parse_anno(&p_vec[1])?,
+        size: parse_expr(indent, &p_vec[2])?,
+        unit: p_vec[3].expect_i64()? as u32,
+        flags: parse_bin_flags(&p_vec[4])?,
+        val: p_vec[5].clone(),
+        next: parse_bin_next(indent, &p_vec[6])?,
+      }))
+    },
+    Some("k_map") => Pattern::Map(Box::new(parse_map(indent, pat)?)),
+    Some("k_bin_end") => { // {k_bin_end, anno}
+      let p_vec = pat.expect_tagged_tuple("k_bin_end", 2)?;
+      Pattern::BinEnd { anno: parse_anno(&p_vec[1])? }
+    },
+    _ => Pattern::Term(pat.clone()),
+  };
+  Ok(p)
+}
+
+
+/// Variables which receive the results of a node, `[]` if there are none
+/// or the results are not used
+fn parse_ret(indent: u32, ret: &FTerm) -> ParseResult<Vec<Expr>> {
+  let mut result = Vec::new();
+  for r in ret.expect_list()? {
+    if r.tuple_tag() != Some("k_var") {
+      return Err(TermError::new("return variable {k_var, ...}", r))
+    }
+    result.push(_parse_expr_2(indent, r)?);
+  }
+  Ok(result)
+}
+
+
+fn parse_seq(indent: u32, kseq: &FTerm) -> ParseResult<Expr> {
   // {k_seq, anno, arg, body}
-  let seq_vec = kseq.get_tuple_vec();
-  assert!(seq_vec[0].is_atom_of("k_seq"));
+  let seq_vec = kseq.expect_tagged_tuple("k_seq", 4)?;
 
   let arg = &seq_vec[2];
-  println!("{}k_seq -> {}", ii(indent), arg);
+  trace!("{}k_seq -> {}", ii(indent), arg);
 
   let ks = KSeq {
-    anno: seq_vec[1].clone(),
-    arg: parse_expr(indent, arg),
-    body: parse_expr(indent + 1, &seq_vec[3]),
+    anno: parse_anno(&seq_vec[1])?,
+    arg: parse_expr(indent, arg)?,
+    body: parse_expr(indent + 1, &seq_vec[3])?,
   };
-  Expr::Seq(Box::new(ks))
+  Ok(Expr::Seq(Box::new(ks)))
 }
 
 
-fn parse_enter(indent: u32, enter: &FTerm) -> Expr {
+fn parse_enter(indent: u32, enter: &FTerm) -> ParseResult<Expr> {
   // {k_enter, anno, op, args}
-  let enter_vec = enter.get_tuple_vec();
-  assert!(enter_vec[0].is_atom_of("k_enter"));
+  let enter_vec = enter.expect_tagged_tuple("k_enter", 4)?;
 
   let op = &enter_vec[2];
   let args = &enter_vec[3];
-  println!("{}k_enter {}({})", ii(indent), op, args);
+  trace!("{}k_enter {}({})", ii(indent), op, args);
 
   let ke = KEnter {
-    anno: enter_vec[1].clone(),
-    op: parse_funref(indent, &op),
-    args: parse_expr_list(indent, args),
+    anno: parse_anno(&enter_vec[1])?,
+    op: parse_funref(indent, &op)?,
+    args: parse_expr_list(indent, args)?,
   };
-  Expr::Enter(Box::new(ke))
+  Ok(Expr::Enter(Box::new(ke)))
 }
 
 
-fn parse_funref(indent: u32, funref: &FTerm) -> FunRef {
-  let kvec = funref.get_tuple_vec();
-  let tag = kvec[0].get_atom_text();
-  match tag.as_ref() {
-    "k_local" => { // {k_local, anno, name, arity}
-      return FunRef::FArity {
-        f: parse_expr(indent, &kvec[2]),
-        arity: parse_expr(indent, &kvec[3])
-      }
+fn parse_funref(indent: u32, funref: &FTerm) -> ParseResult<FunRef> {
+  match funref.tuple_tag() {
+    Some("k_local") => { // {k_local, anno, name, arity}
+      let kvec = funref.expect_tagged_tuple("k_local", 4)?;
+      Ok(FunRef::FArity {
+        f: parse_expr(indent, &kvec[2])?,
+        arity: parse_expr(indent, &kvec[3])?
+      })
     },
-    "k_remote" => { // {k_remote, anno, mod, name, arity}
-      return FunRef::MFArity {
-        m: parse_expr(indent, &kvec[2]),
-        f: parse_expr(indent, &kvec[3]),
-        arity: parse_expr(indent, &kvec[4])
-      }
+    Some("k_remote") => { // {k_remote, anno, mod, name, arity}
+      let kvec = funref.expect_tagged_tuple("k_remote", 5)?;
+      Ok(FunRef::MFArity {
+        m: parse_expr(indent, &kvec[2])?,
+        f: parse_expr(indent, &kvec[3])?,
+        arity: parse_expr(indent, &kvec[4])?
+      })
     },
-    "k_internal" => { // {k_internal, anno, name, arity}
-      return FunRef::Internal(parse_kinternal(kvec))
+    Some("k_internal") => { // {k_internal, anno, name, arity}
+      Ok(FunRef::Internal(parse_kinternal(funref)?))
     },
-    "k_bif" => { // {k_bif, anno, op, args, ret=[]}
-      return FunRef::Bif(
-        Box::new(parse_kcall(indent, kvec))
-      )
-    }
-    other => panic!("Don't know how to parse fun ref {}", other)
+    Some("k_bif") => { // {k_bif, anno, op, args, ret=[]}
+      Ok(FunRef::Bif(
+        Box::new(parse_kcall(indent, funref)?)
+      ))
+    },
+    Some("k_var") => Ok(FunRef::Var(parse_expr(indent, funref)?)),
+    _ => Err(TermError::new("fun reference", funref)),
   }
 }
 
 
-fn parse_kinternal(kvec: Vec<FTerm>) -> MFA {
+fn parse_kinternal(kinternal: &FTerm) -> ParseResult<MFA> {
   // {k_internal, anno, name, arity}
-  MFA::new2(kvec[2].get_atom_text(), kvec[3].get_i64() as usize)
+  let kvec = kinternal.expect_tagged_tuple("k_internal", 4)?;
+  Ok(MFA::new2(kvec[2].expect_atom()?.to_string(), kvec[3].expect_i64()? as usize))
 }
 
 
-fn parse_kcall(indent: u32, kvec: Vec<FTerm>) -> KCall {
+fn parse_make_fun(indent: u32, kbif: &FTerm) -> ParseResult<KMakeFun> {
+  // {k_bif, anno, {k_internal, _, make_fun, N}, [name, arity | free], ret}
+  let kvec = kbif.expect_tagged_tuple("k_bif", 5)?;
+  let args = kvec[3].expect_list()?;
+  if args.len() < 2 {
+    return Err(TermError::new("make_fun name and arity", &kvec[3]))
+  }
+  let name = args[0].expect_tagged_tuple("k_atom", 3)?[2].expect_atom()?;
+  let arity = args[1].expect_tagged_tuple("k_int", 3)?[2].expect_i64()?;
+  let mut free = Vec::with_capacity(args.len() - 2);
+  for v in &args[2..] {
+    free.push(_parse_expr_2(indent, v)?)
+  }
+  if arity < free.len() as i64 {
+    return Err(TermError::new("make_fun arity not less than free vars",
+                              &args[1]))
+  }
+
+  let anno = parse_anno(&kvec[1])?;
+  Ok(KMakeFun {
+    fun: MFA::new2(name.to_string(), arity as usize),
+    free,
+    id: anno.fun_id.clone(),
+    anno,
+    ret: parse_ret(indent, &kvec[4])?,
+  })
+}
+
+
+fn parse_kcall(indent: u32, kcall: &FTerm) -> ParseResult<KCall> {
   // {k_bif, anno, op, args, ret=[]}
   // {k_call, anno, op, args, ret}
-  let op_mfa= parse_funref(indent, &kvec[2]);
-  KCall {
-    anno: kvec[1].clone(),
+  let kvec = match kcall.tuple_tag() {
+    Some("k_bif") => kcall.expect_tagged_tuple("k_bif", 5)?,
+    _ => kcall.expect_tagged_tuple("k_call", 5)?,
+  };
+  let op_mfa= parse_funref(indent, &kvec[2])?;
+  Ok(KCall {
+    anno: parse_anno(&kvec[1])?,
     op: op_mfa,
-    args: parse_expr_list(indent, &kvec[3]),
-    ret: parse_expr_list(indent, &kvec[4]),
-  }
+    args: parse_expr_list(indent, &kvec[3])?,
+    ret: parse_ret(indent, &kvec[4])?,
+  })
 }
 
 
-fn parse_return(indent: u32, ret: &FTerm) -> Expr {
+fn parse_return(indent: u32, ret: &FTerm) -> ParseResult<Expr> {
   // {k_return, anno, args}
-  let ret_vec = ret.get_tuple_vec();
-  assert!(ret_vec[0].is_atom_of("k_return"));
+  let ret_vec = ret.expect_tagged_tuple("k_return", 3)?;
 
   let args = &ret_vec[2];
-  println!("{}k_return -> {}", ii(indent), args);
+  trace!("{}k_return -> {}", ii(indent), args);
 
   let kret = KReturn {
-    anno: ret_vec[1].clone(),
-    args: parse_expr_list(indent, args),
+    anno: parse_anno(&ret_vec[1])?,
+    args: parse_expr_list(indent, args)?,
+  };
+  Ok(Expr::Return(kret))
+}
+
+
+fn parse_try(indent: u32, ktry: &FTerm, tag: &str, arity: usize) -> ParseResult<KTry> {
+  // {k_try, anno, arg, vars, body, evars, handler, ret}
+  // {k_try_enter, anno, arg, vars, body, evars, handler}
+  let try_vec = ktry.expect_tagged_tuple(tag, arity)?;
+
+  trace!("{}{} {{", ii(indent), tag);
+  let arg = parse_expr(indent + 1, &try_vec[2])?;
+  let vars = parse_expr_list(indent, &try_vec[3])?;
+  trace!("{}}} of {:?} {{", ii(indent), vars);
+  let body = parse_expr(indent + 1, &try_vec[4])?;
+
+  let evars = parse_expr_list(indent, &try_vec[5])?;
+  if evars.len() != 3 {
+    return Err(TermError::new("class, reason and stacktrace variables", &try_vec[5]))
+  }
+  trace!("{}}} catch {:?} {{", ii(indent), evars);
+  let handler = parse_expr(indent + 1, &try_vec[6])?;
+  trace!("{}}} % end {}", ii(indent), tag);
+
+  let ret = match try_vec.get(7) {
+    Some(r) => parse_ret(indent, r)?,
+    None => Vec::new(),
   };
-  Expr::Return(kret)
+  let mut evars = evars.into_iter();
+  Ok(KTry {
+    anno: parse_anno(&try_vec[1])?,
+    arg: Box::new(arg),
+    vars,
+    body: Box::new(body),
+    class: evars.next().unwrap(),
+    reason: evars.next().unwrap(),
+    stacktrace: evars.next().unwrap(),
+    handler: Box::new(handler),
+    ret,
+  })
 }
 
 
-fn parse_alt(indent: u32, alt: &FTerm) -> Expr {
+fn parse_catch(indent: u32, kcatch: &FTerm) -> ParseResult<Expr> {
+  // {k_catch, anno, body, ret}
+  let catch_vec = kcatch.expect_tagged_tuple("k_catch", 4)?;
+
+  trace!("{}k_catch {{", ii(indent));
+  let body = parse_expr(indent + 1, &catch_vec[2])?;
+  trace!("{}}} % end catch", ii(indent));
+
+  Ok(Expr::Catch(Box::new(KCatch {
+    anno: parse_anno(&catch_vec[1])?,
+    body,
+    ret: parse_ret(indent, &catch_vec[3])?,
+  })))
+}
+
+
+fn parse_receive(indent: u32, krecv: &FTerm) -> ParseResult<Expr> {
+  // {k_receive, anno, var, body, timeout, action, ret}
+  let recv_vec = krecv.expect_tagged_tuple("k_receive", 7)?;
+
+  let var = parse_expr(indent, &recv_vec[2])?;
+  trace!("{}k_receive {:?} {{", ii(indent), var);
+  let body = parse_expr(indent + 1, &recv_vec[3])?;
+  let timeout = parse_expr(indent, &recv_vec[4])?;
+  trace!("{}}} after {:?} {{", ii(indent), timeout);
+  let action = parse_expr(indent + 1, &recv_vec[5])?;
+  trace!("{}}} % end receive", ii(indent));
+
+  Ok(Expr::Receive(Box::new(KReceive {
+    anno: parse_anno(&recv_vec[1])?,
+    var,
+    body: Box::new(body),
+    timeout,
+    action: Box::new(action),
+    ret: parse_ret(indent, &recv_vec[6])?,
+  })))
+}
+
+
+fn parse_break(indent: u32, kbreak: &FTerm) -> ParseResult<Expr> {
+  // {k_break, anno, args}
+  let break_vec = kbreak.expect_tagged_tuple("k_break", 3)?;
+
+  let args = &break_vec[2];
+  trace!("{}k_break -> {}", ii(indent), args);
+
+  Ok(Expr::Break {
+    anno: parse_anno(&break_vec[1])?,
+    args: parse_expr_list(indent, args)?,
+  })
+}
+
+
+fn parse_alt(indent: u32, alt: &FTerm) -> ParseResult<Expr> {
   // {k_alt, anno, first, then}
-  let alt_vec = alt.get_tuple_vec();
-  assert!(alt_vec[0].is_atom_of("k_alt"));
+  let alt_vec = alt.expect_tagged_tuple("k_alt", 4)?;
 
-  println!("{}k_alt first {{", ii(indent));
+  trace!("{}k_alt first {{", ii(indent));
   let first = &alt_vec[2];
   let kfirst = Box::new(
-    parse_expr(indent + 1, first)
+    parse_expr(indent + 1, first)?
   );
 
-  println!("{}}} k_alt then {{", ii(indent));
+  trace!("{}}} k_alt then {{", ii(indent));
   let then = &alt_vec[3];
-  let kthen = Box::new(parse_expr(indent + 1, then));
-  println!("{}}} % end alt", ii(indent));
+  let kthen = Box::new(parse_expr(indent + 1, then)?);
+  trace!("{}}} % end alt", ii(indent));
 
   let ka = KAlt {
-    anno: alt_vec[1].clone(),
+    anno: parse_anno(&alt_vec[1])?,
     first: kfirst,
     then: kthen,
   };
-  Expr::Alt(ka)
+  Ok(Expr::Alt(ka))
 }
 
-fn parse_select(indent: u32, sel: &FTerm) -> Expr {
+fn parse_select(indent: u32, sel: &FTerm) -> ParseResult<Expr> {
   // Assert kselect contains only type_clauses
   // {k_select, var, types}
-  let sel_vec = sel.get_tuple_vec();
-  assert!(sel_vec[0].is_atom_of("k_select"));
+  let sel_vec = sel.expect_tagged_tuple("k_select", 4)?;
 
   let var = &sel_vec[2];
   let type_clauses = &sel_vec[3];
-  println!("{}k_select {} {{", ii(indent), var);
+  trace!("{}k_select {} {{", ii(indent), var);
   let tclauses = parse_type_clauses(indent + 1,
-                                    type_clauses);
-  println!("{}}} % end select", ii(indent));
+                                    type_clauses)?;
+  trace!("{}}} % end select", ii(indent));
 
   let ks = KSelect {
-    anno: sel_vec[1].clone(),
-    var: parse_expr(indent, &var),
+    anno: parse_anno(&sel_vec[1])?,
+    var: parse_expr(indent, &var)?,
     type_clauses: tclauses,
   };
-  Expr::Select(Box::new(ks))
+  Ok(Expr::Select(Box::new(ks)))
 }
 
 
-fn parse_type_clauses(indent: u32, tclauses: &FTerm) -> Vec<KTypeClause> {
-  let tclauses_vec = tclauses.get_list_vec();
+fn parse_type_clauses(indent: u32, tclauses: &FTerm) -> ParseResult<Vec<KTypeClause>> {
+  let tclauses_vec = tclauses.expect_list()?;
   let mut result = Vec::<KTypeClause>::with_capacity(tclauses_vec.len());
 
   for tclause in tclauses_vec {
-    let tclause_vec = tclause.get_vec();
     // {k_type_clause, anno, type, values}
-    assert!(tclause_vec[0].is_atom_of("k_type_clause"));
+    let tclause_vec = tclause.expect_tagged_tuple("k_type_clause", 4)?;
 
     let typeclause_type = &tclause_vec[2];
     let typeclause_valclauses = &tclause_vec[3];
 
-    println!("{}k_type_clause {} {{", ii(indent), typeclause_type);
-    let vcs = parse_val_clauses(indent + 1, typeclause_valclauses);
+    trace!("{}k_type_clause {} {{", ii(indent), typeclause_type);
+    let vcs = parse_val_clauses(indent + 1, typeclause_valclauses)?;
     let tc = KTypeClause {
-      anno: tclause_vec[1].clone(),
+      anno: parse_anno(&tclause_vec[1])?,
       type_: tclause_vec[2].clone(),
       values: vcs,
     };
     result.push(tc);
-    println!("{}}}", ii(indent))
+    trace!("{}}}", ii(indent))
   }
-  result
+  Ok(result)
 }
 
 
-fn parse_val_clauses(indent: u32, vclauses: &FTerm) -> Vec<KValClause> {
-  let vclause_list = vclauses.get_list_vec();
-  let mut result = Vec::<KValClause>::with_capacity(vclauses.list_size());
+fn parse_val_clauses(indent: u32, vclauses: &FTerm) -> ParseResult<Vec<KValClause>> {
+  let vclause_list = vclauses.expect_list()?;
+  let mut result = Vec::<KValClause>::with_capacity(vclause_list.len());
 
   for vc in vclause_list {
     // {k_val_clause, anno, val, body}
-    let vclause_vec = vc.get_vec();
-    assert!(vclause_vec[0].is_atom_of("k_val_clause"));
+    let vclause_vec = vc.expect_tagged_tuple("k_val_clause", 4)?;
 
     let vclause_val = &vclause_vec[2];
-    println!("{}k_val_clause {} {{", ii(indent), vclause_val);
+    trace!("{}k_val_clause {} {{", ii(indent), vclause_val);
     let vc = KValClause {
-      anno: vclause_vec[1].clone(),
-      val: vclause_vec[2].clone(),
-      body: parse_expr(indent + 1, &vclause_vec[3]),
+      anno: parse_anno(&vclause_vec[1])?,
+      val: parse_pattern(indent, vclause_val)?,
+      body: parse_expr(indent + 1, &vclause_vec[3])?,
     };
     result.push(vc);
-    println!("{}}}", ii(indent));
+    trace!("{}}}", ii(indent));
   }
-  result
+  Ok(result)
 }
 
 
-fn parse_guard(indent: u32, guard: &FTerm) -> Expr {
+fn parse_guard(indent: u32, guard: &FTerm) -> ParseResult<Expr> {
   // {k_guard, anno, clauses}
-  let guard_vec = guard.get_tuple_vec();
-  assert!(guard_vec[0].is_atom_of("k_guard"));
+  let guard_vec = guard.expect_tagged_tuple("k_guard", 3)?;
 
   let gclauses_term = &guard_vec[2];
   let mut clauses = Vec::<KGuardClause>::new();
-  for gclause in gclauses_term.get_list_vec() {
-    println!("{}k_guard {{", ii(indent));
-    let clause = parse_kguard_clauses(indent + 1, &gclause);
+  for gclause in gclauses_term.expect_list()? {
+    trace!("{}k_guard {{", ii(indent));
+    let clause = parse_kguard_clauses(indent + 1, gclause)?;
     clauses.push(clause);
-    println!("{}}} % end guard", ii(indent));
+    trace!("{}}} % end guard", ii(indent));
   }
   let kg = KGuard {
-    anno: guard_vec[1].clone(),
+    anno: parse_anno(&guard_vec[1])?,
     clauses
   };
-  Expr::Guard(kg)
+  Ok(Expr::Guard(kg))
 }
 
 
-fn parse_kguard_clauses(indent: u32, kgc_tuple: &FTerm) -> KGuardClause {
-  assert!(kgc_tuple.is_tuple());
-  let v = kgc_tuple.get_tuple_vec();
-  assert!(v[0].is_atom_of("k_guard_clause"));
+fn parse_kguard_clauses(indent: u32, kgc_tuple: &FTerm) -> ParseResult<KGuardClause> {
+  // {k_guard_clause, anno, guard, body}
+  let v = kgc_tuple.expect_tagged_tuple("k_guard_clause", 4)?;
 
-  println!("{}kguardclause {{", ii(indent));
-  let body = parse_expr(indent + 1, &v[3]);
-  println!("{}}}", ii(indent));
+  trace!("{}kguardclause {{", ii(indent));
+  let body = parse_expr(indent + 1, &v[3])?;
+  trace!("{}}}", ii(indent));
 
-  KGuardClause {
-    anno: v[1].clone(),
-    guard: parse_expr(indent, &v[2]),
+  Ok(KGuardClause {
+    anno: parse_anno(&v[1])?,
+    guard: parse_expr(indent, &v[2])?,
     body,
-  }
+  })
 }
 
 
-fn parse_kguard_break(indent: u32, k_gb: &FTerm) -> Expr {
+fn parse_kguard_break(indent: u32, k_gb: &FTerm) -> ParseResult<Expr> {
   // {k_guard_break, anno, args}
-  let gbvec = k_gb.get_tuple_vec();
-  assert!(gbvec[0].is_atom_of("k_guard_break"));
+  let gbvec = k_gb.expect_tagged_tuple("k_guard_break", 3)?;
 
-  let args = parse_expr_list(indent, &gbvec[2]);
-  println!("{}kguard_break {:?}", ii(indent), args);
-  Expr::GuardBreak {
-    anno: gbvec[1].clone(),
+  let args = parse_expr_list(indent, &gbvec[2])?;
+  trace!("{}kguard_break {:?}", ii(indent), args);
+  Ok(Expr::GuardBreak {
+    anno: parse_anno(&gbvec[1])?,
     args,
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use erl_aotc_parser::parse_nodot;
+  use erl_shared::atom::Atom;
+  use erl_shared::fterm::FTerm;
+  use erl_types::MFA;
+  use kernel::*;
+  use super::{parse_anno, parse_expr, process_module, ParseResult};
+
+
+  fn expr(text: &str) -> ParseResult<Expr> {
+    parse_expr(0, &parse_nodot(text).unwrap())
+  }
+
+
+  fn var_name(e: &Expr) -> &str {
+    match e {
+      Expr::Variable(v) => v.as_str(),
+      other => panic!("expected a variable, got {:?}", other),
+    }
+  }
+
+
+  /// Patterns of all value clauses of a k_select, in order
+  fn select_patterns(e: Expr) -> Vec<Pattern> {
+    match e {
+      Expr::Select(sel) => sel.type_clauses.into_iter()
+        .flat_map(|tc| tc.values.into_iter().map(|v| v.val))
+        .collect(),
+      other => panic!("expected k_select, got {:?}", other),
+    }
+  }
+
+
+  #[test]
+  fn kernel_break() {
+    match expr("{k_break,[12],[{k_var,[],'X'},{k_atom,[],ok}]}").unwrap() {
+      Expr::Break { anno, args } => {
+        assert_eq!(anno.line, Some(12));
+        assert_eq!(args.len(), 2);
+        assert_eq!(var_name(&args[0]), "X");
+        match args[1] {
+          Expr::Atom(a) => assert_eq!(a, "ok"),
+          ref other => panic!("expected an atom, got {:?}", other),
+        }
+      },
+      other => panic!("expected k_break, got {:?}", other),
+    }
+    assert!(expr("{k_break,[]}").is_err());
+  }
+
+
+  #[test]
+  fn kernel_binary_patterns() {
+    let sel = expr("{k_select,[],{k_var,[],13},\
+      [{k_type_clause,[],k_binary,\
+        [{k_val_clause,[],{k_binary,[],{k_var,[],14}},{k_atom,[],a}}]},\
+       {k_type_clause,[],k_bin_end,\
+        [{k_val_clause,[],{k_bin_end,[]},{k_atom,[],b}}]},\
+       {k_type_clause,[],k_bin_int,\
+        [{k_val_clause,[],\
+          {k_bin_int,[],{k_int,[],16},1,[signed,little],-2,{k_var,[],15}},\
+          {k_atom,[],c}}]},\
+       {k_type_clause,[],k_bin_seg,\
+        [{k_val_clause,[],\
+          {k_bin_seg,[],{k_atom,[],all},8,binary,[unsigned,big],{k_var,[],16},[]},\
+          {k_atom,[],d}}]},\
+       {k_type_clause,[],k_atom,\
+        [{k_val_clause,[],{k_atom,[],nope},{k_atom,[],e}}]}]}").unwrap();
+    let pats = select_patterns(sel);
+    assert_eq!(pats.len(), 5);
+    match &pats[0] {
+      Pattern::Binary { segs, .. } => assert_eq!(var_name(segs), "14"),
+      other => panic!("expected k_binary, got {:?}", other),
+    }
+    assert!(matches!(pats[1], Pattern::BinEnd { .. }));
+    match &pats[2] {
+      Pattern::BinInt(i) => {
+        assert_eq!(i.unit, 1);
+        assert_eq!(i.flags, vec![BinFlag::Signed, BinFlag::Little]);
+        assert_eq!(i.val, FTerm::Int64(-2));
+        assert_eq!(var_name(i.next.as_ref().unwrap()), "15");
+      },
+      other => panic!("expected k_bin_int, got {:?}", other),
+    }
+    match &pats[3] {
+      Pattern::BinSeg(s) => {
+        assert_eq!(s.unit, 8);
+        assert_eq!(s.seg_type, BinSegType::Binary);
+        assert_eq!(s.flags, vec![BinFlag::Unsigned, BinFlag::Big]);
+        assert_eq!(var_name(&s.seg), "16");
+        assert!(s.next.is_none());
+      },
+      other => panic!("expected k_bin_seg, got {:?}", other),
+    }
+    assert!(matches!(pats[4], Pattern::Term(_)));
+
+    // Construction, segments are chained through next
+    match expr("{k_binary,[],{k_bin_seg,[],{k_int,[],32},1,float,[big],\
+                  {k_var,[],'F'},{k_bin_seg,[],{k_atom,[],undefined},1,utf8,\
+                  [unsigned,big],{k_int,[],955},{k_bin_end,[]}}}}").unwrap() {
+      Expr::ConstructBinary { segments: Some(s), .. } => {
+        assert_eq!(s.seg_type, BinSegType::Float);
+        assert_eq!(s.flags, vec![BinFlag::Big]);
+        let next = s.next.unwrap();
+        assert_eq!(next.seg_type, BinSegType::Utf8);
+        assert!(next.next.is_none());
+      },
+      other => panic!("expected k_binary, got {:?}", other),
+    }
+
+    assert!(expr("{k_binary,[],{k_bin_seg,[],{k_int,[],8},1,integer,[middle],\
+                   {k_int,[],1},{k_bin_end,[]}}}").is_err());
+    assert!(expr("{k_binary,[],{k_bin_seg,[],{k_int,[],8},1,bits,[],\
+                   {k_int,[],1},{k_bin_end,[]}}}").is_err());
+  }
+
+
+  #[test]
+  fn kernel_try_catch() {
+    let body = "{k_call,[],{k_remote,[],{k_atom,[],erlang},{k_atom,[],abs},1},\
+                  [{k_var,[],'X'}],[{k_var,[],1}]},\
+                [{k_var,[],2}],\
+                {k_break,[],[{k_var,[],2}]},\
+                [{k_var,[],3},{k_var,[],4},{k_var,[],5}],\
+                {k_break,[],[{k_atom,[],error}]}";
+    match expr(&format!("{{k_try,[],{},[{{k_var,[],6}}]}}", body)).unwrap() {
+      Expr::Try(t) => {
+        assert!(matches!(*t.arg, Expr::Call(_)));
+        assert_eq!(var_name(&t.vars[0]), "2");
+        assert!(matches!(*t.body, Expr::Break { .. }));
+        assert_eq!(var_name(&t.class), "3");
+        assert_eq!(var_name(&t.reason), "4");
+        assert_eq!(var_name(&t.stacktrace), "5");
+        assert!(matches!(*t.handler, Expr::Break { .. }));
+        assert_eq!(var_name(&t.ret[0]), "6");
+      },
+      other => panic!("expected k_try, got {:?}", other),
+    }
+    match expr(&format!("{{k_try_enter,[],{}}}", body)).unwrap() {
+      Expr::TryEnter(t) => assert!(t.ret.is_empty()),
+      other => panic!("expected k_try_enter, got {:?}", other),
+    }
+    match expr("{k_catch,[],{k_return,[],[{k_atom,[],ok}]},[{k_var,[],7}]}").unwrap() {
+      Expr::Catch(c) => {
+        assert!(matches!(c.body, Expr::Return(_)));
+        assert_eq!(var_name(&c.ret[0]), "7");
+      },
+      other => panic!("expected k_catch, got {:?}", other),
+    }
+
+    // Wrong arity, and two exception variables instead of three
+    assert!(expr(&format!("{{k_try,[],{}}}", body)).is_err());
+    assert!(expr(&format!("{{k_try_enter,[],{},[]}}", body)).is_err());
+    assert!(expr("{k_try,[],{k_atom,[],a},[],{k_atom,[],b},[{k_var,[],3},{k_var,[],4}],\
+                   {k_atom,[],c},[]}").is_err());
+    assert!(expr("{k_catch,[],{k_atom,[],a}}").is_err());
+  }
+
+
+  #[test]
+  fn kernel_receive() {
+    let recv = expr("{k_receive,[],{k_var,[],1},\
+                      {k_seq,[],{k_receive_accept,[]},{k_return,[],[{k_atom,[],ok}]}},\
+                      {k_atom,[],infinity},\
+                      {k_seq,[],{k_receive_next,[]},{k_return,[],[]}},\
+                      [{k_var,[],2}]}").unwrap();
+    match recv {
+      Expr::Receive(r) => {
+        assert_eq!(var_name(&r.var), "1");
+        match *r.body {
+          Expr::Seq(ref s) =>
+            assert!(matches!(s.arg, Expr::ReceiveAccept { .. })),
+          ref other => panic!("expected k_seq, got {:?}", other),
+        }
+        match r.timeout {
+          Expr::Atom(a) => assert_eq!(a, "infinity"),
+          ref other => panic!("expected an atom, got {:?}", other),
+        }
+        match *r.action {
+          Expr::Seq(ref s) =>
+            assert!(matches!(s.arg, Expr::ReceiveNext { .. })),
+          ref other => panic!("expected k_seq, got {:?}", other),
+        }
+        assert_eq!(var_name(&r.ret[0]), "2");
+      },
+      other => panic!("expected k_receive, got {:?}", other),
+    }
+    assert!(expr("{k_receive,[],{k_var,[],1},{k_atom,[],a},{k_int,[],0},\
+                   {k_atom,[],b}}").is_err());
+    assert!(expr("{k_receive_accept,[],x}").is_err());
+  }
+
+
+  #[test]
+  fn kernel_anno() {
+    let anno = |text: &str| parse_anno(&parse_nodot(text).unwrap());
+
+    let a = anno("{k,['X',1],[2],[10,{file,\"m.erl\"},compiler_generated,\
+                   {id,{0,7,'-f/1-fun-0-'}},{function_name,{f,1}}]}").unwrap();
+    assert_eq!(a.used, vec![Atom::new("X"), Atom::new("1")]);
+    assert_eq!(a.defined, vec![Atom::new("2")]);
+    assert_eq!(a.line, Some(10));
+    assert_eq!(a.file, Some("m.erl".to_string()));
+    assert_eq!(a.flags, vec![Atom::new("compiler_generated")]);
+    assert_eq!(a.fun_id, Some(FunId { index: 0, uniq: 7, name: Atom::new("-f/1-fun-0-") }));
+    assert_eq!(a.other, vec![parse_nodot("{function_name,{f,1}}").unwrap()]);
+
+    // Only the user part, with a location or printed as a string by ~p
+    assert_eq!(anno("[{location,{5,3}}]").unwrap().line, Some(5));
+    assert_eq!(anno("\"\\n\"").unwrap().line, Some(10));
+    assert_eq!(anno("[]").unwrap(), KAnno::default());
+
+    assert!(anno("[-1]").is_err());
+    assert!(anno("[4294967296]").is_err());
+    assert!(anno("[{location,{-5,1}}]").is_err());
+    assert!(anno("[{id,{-1,0,f}}]").is_err());
+    assert!(anno("[{id,{0,0}}]").is_err());
+    assert!(anno("{k,[{x}],[],[]}").is_err());
+  }
+
+
+  #[test]
+  fn kernel_ret() {
+    let ret_names = |e: &[Expr]| e.iter().map(|r| var_name(r).to_string()).collect::<Vec<_>>();
+
+    match expr("{k_match,[],[{k_var,[],1}],{k_break,[],[]},[{k_var,[],2},{k_var,[],'R'}]}").unwrap() {
+      Expr::Match(m) => assert_eq!(ret_names(&m.ret), vec!["2", "R"]),
+      other => panic!("expected k_match, got {:?}", other),
+    }
+    match expr("{k_guard_match,[],[],{k_break,[],[]},[]}").unwrap() {
+      Expr::GuardMatch(m) => assert!(m.ret.is_empty()),
+      other => panic!("expected k_guard_match, got {:?}", other),
+    }
+    match expr("{k_call,[],{k_local,[],g,0},[],[{k_var,[],3}]}").unwrap() {
+      Expr::Call(c) => assert_eq!(ret_names(&c.ret), vec!["3"]),
+      other => panic!("expected k_call, got {:?}", other),
+    }
+
+    // Only variables can receive results
+    assert!(expr("{k_match,[],[],{k_break,[],[]},[{k_atom,[],x}]}").is_err());
+    assert!(expr("{k_call,[],{k_local,[],g,0},[],{k_var,[],3}}").is_err());
+    assert!(expr("{k_guard_match,[],[],{k_break,[],[]},[{k_int,[],1}]}").is_err());
+  }
+
+
+  #[test]
+  fn kernel_map() {
+    match expr("{k_map,[],{k_literal,[],#{}},assoc,\
+                  [{k_map_pair,[],{k_atom,[],a},{k_var,[],1}},\
+                   {k_map_pair,[],{k_int,[],2},{k_nil,[]}}]}").unwrap() {
+      Expr::Map(m) => {
+        assert_eq!(m.op, MapOp::Assoc);
+        match m.var {
+          Expr::Value { ref val, .. } => assert_eq!(*val, FTerm::Map(vec![])),
+          ref other => panic!("expected k_literal, got {:?}", other),
+        }
+        assert_eq!(m.pairs.len(), 2);
+        assert_eq!(var_name(&m.pairs[0].val), "1");
+        assert!(matches!(m.pairs[1].key, Expr::Int64(2)));
+      },
+      other => panic!("expected k_map, got {:?}", other),
+    }
+
+    // Update M#{a := 1}
+    match expr("{k_map,[],{k_var,[],'M'},exact,\
+                  [{k_map_pair,[],{k_atom,[],a},{k_int,[],1}}]}").unwrap() {
+      Expr::Map(m) => {
+        assert_eq!(m.op, MapOp::Exact);
+        assert_eq!(var_name(&m.var), "M");
+      },
+      other => panic!("expected k_map, got {:?}", other),
+    }
+
+    let sel = expr("{k_select,[],{k_var,[],'M'},\
+      [{k_type_clause,[],k_map,\
+        [{k_val_clause,[],\
+          {k_map,[],{k_var,[],'M'},exact,[{k_map_pair,[],{k_atom,[],key},{k_var,[],1}}]},\
+          {k_atom,[],found}}]}]}").unwrap();
+    match &select_patterns(sel)[0] {
+      Pattern::Map(m) => {
+        assert_eq!(m.op, MapOp::Exact);
+        assert_eq!(var_name(&m.pairs[0].val), "1");
+      },
+      other => panic!("expected k_map, got {:?}", other),
+    }
+
+    assert!(expr("{k_map,[],{k_var,[],'M'},update,[]}").is_err());
+    assert!(expr("{k_map,[],{k_var,[],'M'},exact,[{k_atom,[],a}]}").is_err());
+  }
+
+
+  /// Module with a closure and the function lifted from its body
+  const FUN_MODULE: &str = "{k_mdef,[],m,[{f,1}],[],\
+    [{k_fdef,{k,[],[],[1]},f,1,[{k_var,[],'X'}],\
+      {k_seq,[],\
+       {k_bif,{k,['X'],[3],[{id,{0,5,'-f/1-fun-0-'}},2]},\
+        {k_internal,[],make_fun,3},\
+        [{k_atom,[],'-f/1-fun-0-'},{k_int,[],2},{k_var,[],'X'}],\
+        [{k_var,[],3}]},\
+       {k_enter,[],{k_var,[],3},[{k_int,[],1}]}}},\
+     {k_fdef,{k,[],[],[{id,{0,5,'-f/1-fun-0-'}},2]},'-f/1-fun-0-',2,\
+      [{k_var,[],1},{k_var,[],'X'}],\
+      {k_return,[],[{k_var,[],'X'}]}}]}";
+
+
+  #[test]
+  fn kernel_make_fun() {
+    let m = process_module(&parse_nodot(FUN_MODULE).unwrap()).unwrap();
+
+    let f = &m.funs[&MFA::new2("f".to_string(), 1)];
+    assert!(f.fun_id.is_none());
+    let (mf, enter) = match f.k_code {
+      Expr::Seq(ref s) => match (&s.arg, &s.body) {
+        (Expr::MakeFun(mf), Expr::Enter(e)) => (mf, e),
+        other => panic!("expected make_fun and k_enter, got {:?}", other),
+      },
+      ref other => panic!("expected k_seq, got {:?}", other),
+    };
+    assert_eq!(mf.fun, MFA::new2("-f/1-fun-0-".to_string(), 2));
+    assert_eq!(mf.arity(), Some(1));
+    assert_eq!(var_name(&mf.free[0]), "X");
+    assert_eq!(var_name(&mf.ret[0]), "3");
+    let id = mf.id.clone().unwrap();
+    assert_eq!((id.index, id.uniq), (0, 5));
+
+    // Linked to the lifted function, which has the same id
+    let lifted = m.lifted_fun(mf).unwrap();
+    assert_eq!(lifted.funarity, mf.fun);
+    assert_eq!(lifted.fun_id, mf.id);
+
+    // The closure is called through the variable
+    match enter.op {
+      FunRef::Var(ref v) => assert_eq!(var_name(v), "3"),
+      ref other => panic!("expected a fun variable, got {:?}", other),
+    }
+
+    // Other internal bifs stay calls, the lifted arity covers the free vars
+    assert!(matches!(expr("{k_bif,[],{k_internal,[],recv_peek_message,0},[],[]}").unwrap(),
+                     Expr::Bif(_)));
+    assert!(expr("{k_bif,[],{k_internal,[],make_fun,4},\
+                   [{k_atom,[],g},{k_int,[],1},{k_var,[],'A'},{k_var,[],'B'}],[]}").is_err());
+    assert!(expr("{k_bif,[],{k_internal,[],make_fun,1},[{k_atom,[],g}],[]}").is_err());
+
+    let unchecked = KMakeFun {
+      anno: KAnno::default(),
+      fun: MFA::new2("g".to_string(), 0),
+      free: vec![Expr::Nil],
+      id: None,
+      ret: vec![],
+    };
+    assert_eq!(unchecked.arity(), None);
+    assert!(m.lifted_fun(&unchecked).is_none());
+  }
+
+
+  #[test]
+  fn kernel_atoms() {
+    // Module and function names of fun literals are atoms too
+    let m = process_module(&parse_nodot("{k_mdef,[],m,[{f,0}],[],\
+      [{k_fdef,{k,[],[],[]},f,0,[],\
+        {k_return,[],[{k_literal,[],{#Fun<lists.map.2>,#Fun<erl_eval.6.1>}}]}}]}")
+      .unwrap()).unwrap();
+    let names: Vec<&str> = m.atoms.iter().map(|a| a.as_str()).collect();
+    assert_eq!(names, vec!["m", "f", "lists", "map", "erl_eval"]);
+  }
+
+
+  #[cfg(feature = "serialize")]
+  #[test]
+  fn kernel_serialize() {
+    extern crate serde_json;
+
+    let m = process_module(&parse_nodot(FUN_MODULE).unwrap()).unwrap();
+    let json = serde_json::to_string(&m).unwrap();
+    // Functions are a list, each one carries its name and arity
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["funs"].as_array().map(|funs| funs.len()), Some(2));
+
+    let back: Module = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&back).unwrap(), json);
+    assert_eq!(back.atoms.index_of(Atom::new("-f/1-fun-0-")), Some(2));
+    let f = &back.funs[&MFA::new2("f".to_string(), 1)];
+    let lifted = match f.k_code {
+      Expr::Seq(ref s) => match s.arg {
+        Expr::MakeFun(ref mf) => back.lifted_fun(mf).unwrap(),
+        ref other => panic!("expected make_fun, got {:?}", other),
+      },
+      ref other => panic!("expected k_seq, got {:?}", other),
+    };
+    assert!(lifted.fun_id.is_some());
   }
 }
@@ -2,11 +2,16 @@
 extern crate erl_aotc_parser;
 extern crate erl_shared;
 extern crate llvm_sys as llvm;
+#[cfg(feature = "serialize")]
+extern crate serde;
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
 //#[macro_use]
 //extern crate matches;
 
 mod codegen;
-mod erl_types;
-mod kernel;
+pub mod erl_types;
+pub mod kernel;
 pub mod aotc_main;
 pub mod ll_types;
@@ -0,0 +1,228 @@
+//! Builds bitstrings from segments of a binary literal such as
+//! `<<1, 2, "abc", 5:3, "ä"/utf8, 1.5/float>>`.
+
+use lexer::LexicalError;
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive};
+
+
+/// Longest segment a literal may have, in bits
+const MAX_SEGMENT_BITS: usize = 1 << 24;
+
+
+/// Value of one segment before it is encoded
+pub enum SegValue {
+  Int(BigInt),
+  Float(f64),
+  Str(String),
+}
+
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum SegType {
+  Integer,
+  Float,
+  Utf8,
+  Utf16,
+  Utf32,
+}
+
+
+/// Segment type specifiers following `/`, like `/integer-little-unit:8`
+pub struct SegSpec {
+  pub seg_type: Option<SegType>,
+  pub size: Option<usize>,
+  pub unit: Option<usize>,
+  pub little: bool,
+}
+
+
+impl SegSpec {
+  pub fn new() -> SegSpec {
+    SegSpec { seg_type: None, size: None, unit: None, little: false }
+  }
+
+
+  /// Apply one word of a type specifier list, returns false if the word is
+  /// not known.
+  pub fn apply(&mut self, word: &str) -> bool {
+    match word {
+      "integer" => self.seg_type = Some(SegType::Integer),
+      "float" => self.seg_type = Some(SegType::Float),
+      "utf8" => self.seg_type = Some(SegType::Utf8),
+      "utf16" => self.seg_type = Some(SegType::Utf16),
+      "utf32" => self.seg_type = Some(SegType::Utf32),
+      "big" => self.little = false,
+      "little" => self.little = true,
+      "native" => self.little = cfg!(target_endian = "little"),
+      "signed" | "unsigned" => {}, // does not change how a value is stored
+      _ => return false,
+    }
+    true
+  }
+}
+
+
+/// Accumulates bits, most significant bit of each byte first
+pub struct BitWriter {
+  pub data: Vec<u8>,
+  pub bit_size: usize,
+}
+
+
+impl BitWriter {
+  pub fn new() -> BitWriter {
+    BitWriter { data: Vec::new(), bit_size: 0 }
+  }
+
+
+  /// Append the `n` lowest bits of `bits`, `n` is at most 8
+  fn push_bits(&mut self, bits: u8, n: usize) {
+    let used = self.bit_size % 8;
+    let bits = ((bits as u16) << (16 - n)) >> used;
+    if used == 0 {
+      self.data.push(0);
+    }
+    let last = self.data.len() - 1;
+    self.data[last] |= (bits >> 8) as u8;
+    if used + n > 8 {
+      self.data.push(bits as u8);
+    }
+    self.bit_size += n;
+  }
+
+
+  fn push_bytes(&mut self, bytes: &[u8]) {
+    if self.bit_size.is_multiple_of(8) {
+      self.data.extend_from_slice(bytes);
+      self.bit_size += bytes.len() * 8;
+    } else {
+      for b in bytes {
+        self.push_bits(*b, 8)
+      }
+    }
+  }
+
+
+  /// Store `nbits` lowest bits of a value in two's complement. Little endian
+  /// order is only possible for whole bytes.
+  fn push_int(&mut self, val: &BigInt, nbits: usize,
+              little: bool) -> Result<(), LexicalError> {
+    if little && !nbits.is_multiple_of(8) {
+      return Err(LexicalError::BadBinary)
+    }
+    // Big endian bytes holding the value, sign extended or cut to the size
+    let nbytes = nbits.div_ceil(8);
+    let mut bytes = val.to_signed_bytes_be();
+    if bytes.len() > nbytes {
+      bytes.drain(..bytes.len() - nbytes);
+    } else {
+      let fill = if val.is_negative() { 0xff } else { 0 };
+      let mut wide = vec![fill; nbytes - bytes.len()];
+      wide.extend_from_slice(&bytes);
+      bytes = wide;
+    }
+    if little {
+      bytes.reverse();
+      self.push_bytes(&bytes);
+    } else if let Some((first, rest)) = bytes.split_first() {
+      // The first byte holds the bits above the last whole byte
+      let head = nbits - rest.len() * 8;
+      self.push_bits(*first, head);
+      self.push_bytes(rest);
+    }
+    Ok(())
+  }
+
+
+  fn push_float(&mut self, val: f64, nbits: usize,
+                little: bool) -> Result<(), LexicalError> {
+    let mut bytes = match nbits {
+      32 => {
+        let b = (val as f32).to_bits();
+        vec![(b >> 24) as u8, (b >> 16) as u8, (b >> 8) as u8, b as u8]
+      },
+      64 => {
+        let b = val.to_bits();
+        (0..8).rev().map(|i| (b >> (i * 8)) as u8).collect()
+      },
+      _ => return Err(LexicalError::BadBinary),
+    };
+    if little { bytes.reverse() }
+    self.push_bytes(&bytes);
+    Ok(())
+  }
+
+
+  fn push_char(&mut self, code: u32, seg_type: SegType,
+               little: bool) -> Result<(), LexicalError> {
+    let ch = match ::std::char::from_u32(code) {
+      Some(c) => c,
+      None => return Err(LexicalError::BadBinary),
+    };
+    match seg_type {
+      SegType::Utf8 => {
+        let mut buf = [0u8; 4];
+        self.push_bytes(ch.encode_utf8(&mut buf).as_bytes());
+      },
+      SegType::Utf16 => {
+        let mut buf = [0u16; 2];
+        for unit in ch.encode_utf16(&mut buf).iter() {
+          self.push_int(&BigInt::from(*unit), 16, little)?;
+        }
+      },
+      _ => self.push_int(&BigInt::from(code), 32, little)?,
+    }
+    Ok(())
+  }
+
+
+  /// Encode a segment value according to its type specifiers
+  pub fn push_segment(&mut self, val: SegValue,
+                      spec: &SegSpec) -> Result<(), LexicalError> {
+    let seg_type = match (spec.seg_type, &val) {
+      (Some(t), _) => t,
+      (None, SegValue::Float(_)) => SegType::Float,
+      (None, _) => SegType::Integer,
+    };
+    let default_size = if seg_type == SegType::Float { 64 } else { 8 };
+    let size = spec.size.unwrap_or(default_size);
+    let nbits = match size.checked_mul(spec.unit.unwrap_or(1)) {
+      Some(n) if n <= MAX_SEGMENT_BITS => n,
+      _ => return Err(LexicalError::BadBinary),
+    };
+
+    match (seg_type, val) {
+      (SegType::Integer, SegValue::Int(i)) => self.push_int(&i, nbits, spec.little),
+      (SegType::Integer, SegValue::Str(s)) => {
+        // Each character is a separate integer segment
+        for c in s.chars() {
+          self.push_int(&BigInt::from(c as u32), nbits, spec.little)?;
+        }
+        Ok(())
+      },
+      (SegType::Float, SegValue::Float(f)) => self.push_float(f, nbits, spec.little),
+      (SegType::Float, SegValue::Int(i)) => {
+        let f = match i.to_f64() {
+          Some(f) => f,
+          None => return Err(LexicalError::BadBinary),
+        };
+        self.push_float(f, nbits, spec.little)
+      },
+      (t, SegValue::Int(i)) if t != SegType::Float => {
+        let code = match i.to_u32() {
+          Some(c) => c,
+          None => return Err(LexicalError::BadBinary),
+        };
+        self.push_char(code, t, spec.little)
+      },
+      (t, SegValue::Str(s)) if t != SegType::Float => {
+        for c in s.chars() {
+          self.push_char(c as u32, t, spec.little)?;
+        }
+        Ok(())
+      },
+      _ => Err(LexicalError::BadBinary),
+    }
+  }
+}
@@ -0,0 +1,76 @@
+/// Reading files with several dotted terms, like `file:consult/1` does for
+/// `.config`, `.app` and `rebar.config` files.
+
+use erl_shared::fterm::FTerm;
+use erlang_term::DottedTermParser;
+use error::{ErrorKind, ParseError};
+use lexer::{Lexer, SpannedToken};
+use token::Token;
+
+
+/// Iterator over `Term.` entries of the input, parses one term at a time so
+/// that a big file does not have to be held as terms in memory all at once.
+/// Stops after the first error.
+pub struct ConsultIter<'input> {
+  input: &'input str,
+  lexer: Lexer<'input>,
+  parser: DottedTermParser,
+  failed: bool,
+}
+
+
+impl<'input> ConsultIter<'input> {
+  pub fn new(input: &'input str) -> ConsultIter<'input> {
+    ConsultIter {
+      input,
+      lexer: Lexer::new(input),
+      parser: DottedTermParser::new(),
+      failed: false,
+    }
+  }
+
+
+  /// Collect tokens of the next term up to and including its dot. Dots inside
+  /// floats, pids and such are part of their tokens, so the first `Dot` token
+  /// always terminates the term.
+  fn next_term_tokens(&mut self) -> Result<Vec<SpannedToken>, ParseError> {
+    let mut tokens = Vec::new();
+    while let Some(tok) = self.lexer.next() {
+      match tok {
+        Ok(t) => {
+          let is_dot = t.1 == Token::Dot;
+          tokens.push(t);
+          if is_dot { break }
+        },
+        Err(e) => return Err(ParseError::new(self.input,
+                                             ErrorKind::Lexical(e.error),
+                                             e.position)),
+      }
+    }
+    Ok(tokens)
+  }
+}
+
+
+impl<'input> Iterator for ConsultIter<'input> {
+  type Item = Result<FTerm, ParseError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed { return None }
+
+    let tokens = match self.next_term_tokens() {
+      Ok(ref t) if t.is_empty() => return None, // end of input
+      Ok(t) => t,
+      Err(e) => {
+        self.failed = true;
+        return Some(Err(e))
+      },
+    };
+
+    let input = self.input;
+    let result = self.parser.parse(tokens.into_iter().map(Ok))
+      .map_err(|e| ParseError::from_lalrpop(input, e));
+    if result.is_err() { self.failed = true }
+    Some(result)
+  }
+}
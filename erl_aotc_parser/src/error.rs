@@ -0,0 +1,93 @@
+/// Errors returned from the public parse functions, carry a line:column
+/// position and an excerpt of the input around it.
+
+use lalrpop_util;
+use lexer::{LexerError, LexicalError};
+use position::Position;
+use std::fmt;
+use token::Token;
+
+
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+  Lexical(LexicalError),
+  InvalidToken,
+  UnexpectedToken { token: Token, expected: Vec<String> },
+  UnexpectedEndOfFile { expected: Vec<String> },
+  ExtraToken(Token),
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+  pub kind: ErrorKind,
+  pub position: Position,
+  /// Source line where the error happened and a `^` marker under the column
+  pub excerpt: String,
+}
+
+
+// How many characters around the error to show in the excerpt
+const EXCERPT_HALF_WIDTH: usize = 60;
+
+
+impl ParseError {
+  pub fn new(input: &str, kind: ErrorKind, position: Position) -> ParseError {
+    ParseError { kind, position, excerpt: make_excerpt(input, position) }
+  }
+
+
+  /// Convert an error returned from the generated parser
+  pub fn from_lalrpop(
+    input: &str,
+    err: lalrpop_util::ParseError<Position, Token, LexerError>) -> ParseError
+  {
+    use lalrpop_util::ParseError as LpError;
+    match err {
+      LpError::InvalidToken { location } =>
+        ParseError::new(input, ErrorKind::InvalidToken, location),
+      LpError::UnrecognizedToken { token: Some((start, token, _)), expected } =>
+        ParseError::new(input, ErrorKind::UnexpectedToken { token, expected }, start),
+      LpError::UnrecognizedToken { token: None, expected } =>
+        ParseError::new(input, ErrorKind::UnexpectedEndOfFile { expected },
+                        Position::end_of(input)),
+      LpError::ExtraToken { token: (start, token, _) } =>
+        ParseError::new(input, ErrorKind::ExtraToken(token), start),
+      LpError::User { error } =>
+        ParseError::new(input, ErrorKind::Lexical(error.error), error.position),
+    }
+  }
+}
+
+
+/// Cut the line containing `pos` from the input, shortened to a window around
+/// the column for very long lines, and put a `^` under the column.
+fn make_excerpt(input: &str, pos: Position) -> String {
+  let line = input.lines().nth(pos.line - 1).unwrap_or("");
+  let skip = (pos.column - 1).saturating_sub(EXCERPT_HALF_WIDTH);
+  let shown: String = line.chars().skip(skip).take(EXCERPT_HALF_WIDTH * 2).collect();
+  let marker = " ".repeat(pos.column - 1 - skip);
+  format!("{}\n{}^", shown, marker)
+}
+
+
+impl fmt::Display for ErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ErrorKind::Lexical(e) => write!(f, "{:?}", e),
+      ErrorKind::InvalidToken => write!(f, "invalid token"),
+      ErrorKind::UnexpectedToken { token, expected } =>
+        write!(f, "unexpected {:?}, expected one of: {}", token, expected.join(" ")),
+      ErrorKind::UnexpectedEndOfFile { expected } =>
+        write!(f, "unexpected end of input, expected one of: {}", expected.join(" ")),
+      ErrorKind::ExtraToken(token) => write!(f, "extra token {:?}", token),
+    }
+  }
+}
+
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}\n{}", self.position, self.kind, self.excerpt)
+  }
+}
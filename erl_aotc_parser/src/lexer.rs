@@ -1,4 +1,7 @@
 // for numeric types ::from_str()
+use bitstring::{BitWriter, SegSpec, SegValue};
+use erl_shared::fterm::{FTerm, Node};
+use num_bigint::BigInt;
 use position::Position;
 use std::str::CharIndices;
 use std::str::FromStr;
@@ -7,32 +10,51 @@ use token::Token;
 #[derive(Debug, Copy, Clone)]
 pub enum LexicalError {
   UnterminatedStringLiteral,
+  UnterminatedAtomLiteral,
   UnexpectedEscapeCode(char),
   UnexpectedEndOfFile,
   Unexpected(char),
   Expected(char),
+  BadNumber,
+  BadBinary,
 }
 
 
 pub type Spanned<Token, Loc, Error> = Result<(Loc, Token, Loc), Error>;
 
-pub type SpannedToken = (usize, Token, usize);
+pub type SpannedToken = (Position, Token, Position);
+
+
+/// Lexical error with the position in the input where it was detected
+#[derive(Debug, Copy, Clone)]
+pub struct LexerError {
+  pub error: LexicalError,
+  pub position: Position,
+}
 
 type InputChar = (usize, char);
 
 pub struct Lexer<'input> {
-  // Input character index (not very useful for error reporting)
-  current_index: usize,
+  // Position of the next character to be consumed
+  position: Position,
+  // Position where the token being read has started
+  token_start: Position,
   input: CharIndices<'input>,
 
   // This contains 0, 1 or more InputChar's consumed from input.
   // Lazily populated one by one, or when an InputChar is unread
   upcoming: Vec<InputChar>,
-  // This contains already consumed InputChar's
-  past: Vec<InputChar>,
+  // This contains few last consumed InputChar's with their positions
+  past: Vec<(InputChar, Position)>,
+  // Emit Token::Comment instead of skipping comments
+  keep_comments: bool,
 }
 
 
+// How many consumed characters are remembered for `un_consume`
+const MAX_PAST: usize = 8;
+
+
 impl<'input> Lexer<'input> {
   fn err<T>(&self, e: LexicalError) -> Result<T, LexicalError> {
     Err(e)
@@ -49,10 +71,35 @@ impl<'input> Lexer<'input> {
 
     Lexer {
       input,
-      current_index: 0,
+      position: Position::new(),
+      token_start: Position::new(),
       upcoming: upc,
       past: Vec::new(),
+      keep_comments: false,
+    }
+  }
+
+
+  /// Create a lexer which returns `%` comments as `Token::Comment` with the
+  /// text after `%`, for tooling which wants to see them. The term parser
+  /// does not accept comment tokens, use `Lexer::new` for parsing.
+  pub fn with_comments(input_str: &'input str) -> Self {
+    let mut lexr = Lexer::new(input_str);
+    lexr.keep_comments = true;
+    lexr
+  }
+
+
+  /// Having consumed `%`, read the comment text until the end of line. The
+  /// newline is left in the input.
+  fn comment(&mut self) -> String {
+    let mut out_str = String::new();
+    while let Some((_, ch)) = self.look_ahead() {
+      if ch == '\n' { break }
+      out_str.push(ch);
+      self.consume();
     }
+    out_str
   }
 
 
@@ -60,12 +107,12 @@ impl<'input> Lexer<'input> {
     let mut out_str = String::new();
     out_str.reserve(32);
 
-    let start = self.current_index;
+    let start = self.token_start;
 
-    while let Some((pos, ch)) = self.consume() {
+    while let Some((_, ch)) = self.consume() {
       match ch {
         '\\' => out_str.push(self.escape_code()?),
-        '"' => return Ok((start, Token::StringLiteral(out_str), pos)),
+        '"' => return Ok((start, Token::StringLiteral(out_str), self.position)),
         ch => out_str.push(ch),
       } // match my_next
     } // while let some
@@ -77,13 +124,17 @@ impl<'input> Lexer<'input> {
   fn quoted_atom_literal(&mut self) -> Result<SpannedToken, LexicalError> {
     let mut out_str = String::new();
     out_str.reserve(20);
-    let start = self.current_index;
+    let start = self.token_start;
 
-    while let Some((pos, ch)) = self.consume() {
-      if ch != '\'' { out_str.push(ch) }
-      else { break; }
+    while let Some((_, ch)) = self.consume() {
+      match ch {
+        '\\' => out_str.push(self.escape_code()?),
+        '\'' => return Ok((start, Token::AtomLiteral(out_str), self.position)),
+        ch => out_str.push(ch),
+      }
     } // while let some
-    Ok((start, Token::AtomLiteral(out_str), self.current_index))
+
+    Err(LexicalError::UnterminatedAtomLiteral)
   }
 
 
@@ -91,65 +142,348 @@ impl<'input> Lexer<'input> {
     let mut out_str = String::new();
     out_str.reserve(10);
     out_str.push(first);
-    let start = self.current_index;
+    let start = self.token_start;
 
-    while let Some((pos, ch)) = self.consume() {
+    while let Some((_, ch)) = self.consume() {
       if is_atom_char(ch) {
         out_str.push(ch);
       } else {
         self.un_consume();
-        return Ok((start, Token::AtomLiteral(out_str), self.current_index));
+        return Ok((start, Token::AtomLiteral(out_str), self.position));
       }
     };
-    Ok((start, Token::AtomLiteral(out_str), self.current_index))
+    Ok((start, Token::AtomLiteral(out_str), self.position))
   }
 
 
-  // Having found opening "<<" - parse the following binary string
+  /// Having consumed `<` with a digit ahead, read a pid `<0.85.0>`
+  fn pid_literal(&mut self) -> Result<SpannedToken, LexicalError> {
+    let start = self.token_start;
+    let node = self.decimal_u32()?;
+    self.consume_expect('.')?;
+    let id = self.decimal_u32()?;
+    self.consume_expect('.')?;
+    let serial = self.decimal_u32()?;
+    self.consume_expect('>')?;
+    let pid = FTerm::Pid { node: Node::Index(node), id, serial };
+    Ok((start, Token::PidLiteral(pid), self.position))
+  }
+
+
+  /// Having consumed `#`, read one of `#Ref<0.1.2.3>`, `#Port<0.5>`,
+  /// `#Fun<m.f.1>` or `#Fun<m.index.uniq>`.
+  fn hash_literal(&mut self) -> Result<SpannedToken, LexicalError> {
+    let start = self.token_start;
+    let mut kind = String::new();
+    while let Some((_, ch)) = self.look_ahead() {
+      if !ch.is_ascii_alphabetic() { break }
+      kind.push(ch);
+      self.consume();
+    }
+    self.consume_expect('<')?;
+
+    let tok = match kind.as_ref() {
+      "Ref" => {
+        let node = Node::Index(self.decimal_u32()?);
+        let mut ids = Vec::new();
+        while self.is_char_ahead('.') {
+          self.consume();
+          ids.push(self.decimal_u32()?);
+        }
+        Token::RefLiteral(FTerm::Reference { node, ids })
+      },
+      "Port" => {
+        let node = Node::Index(self.decimal_u32()?);
+        self.consume_expect('.')?;
+        let id = self.decimal()?;
+        Token::PortLiteral(FTerm::Port { node, id })
+      },
+      "Fun" => {
+        let module = self.fun_name_part()?;
+        self.consume_expect('.')?;
+        let fun = if self.is_digit_ahead() {
+          // Anonymous fun: module.index.uniq
+          let index = self.decimal_u32()?;
+          self.consume_expect('.')?;
+          let uniq = self.decimal_u32()?;
+          FTerm::LocalFun { module, index, uniq, env: None }
+        } else {
+          // Export fun: module.function.arity
+          let fun = self.fun_name_part()?;
+          self.consume_expect('.')?;
+          let arity = self.decimal_u32()?;
+          FTerm::ExportFun { module, fun, arity }
+        };
+        Token::FunLiteral(fun)
+      },
+      _ => return self.err(LexicalError::Unexpected('#')),
+    };
+    self.consume_expect('>')?;
+    Ok((start, tok, self.position))
+  }
+
+
+  /// Read an atom inside `#Fun<...>`, which may be 'quoted'
+  fn fun_name_part(&mut self) -> Result<String, LexicalError> {
+    if self.is_char_ahead('\'') {
+      self.consume();
+      if let (_, Token::AtomLiteral(s), _) = self.quoted_atom_literal()? {
+        return Ok(s)
+      }
+    }
+    let mut out_str = String::new();
+    while let Some((_, ch)) = self.look_ahead() {
+      if !is_atom_char(ch) { break }
+      out_str.push(ch);
+      self.consume();
+    }
+    if out_str.is_empty() {
+      return self.err(LexicalError::Expected('.'))
+    }
+    Ok(out_str)
+  }
+
+
+  /// Read an unsigned decimal number, at least one digit
+  fn decimal(&mut self) -> Result<u64, LexicalError> {
+    let mut out_str = String::new();
+    self.digits(&mut out_str);
+    match u64::from_str(out_str.as_str()) {
+      Ok(val) => Ok(val),
+      Err(_) => self.err(LexicalError::BadNumber),
+    }
+  }
+
+
+  /// A decimal which must fit into u32, like the numbers in `<0.85.0>`
+  fn decimal_u32(&mut self) -> Result<u32, LexicalError> {
+    let val = self.decimal()?;
+    if val > u32::max_value() as u64 {
+      return self.err(LexicalError::BadNumber)
+    }
+    Ok(val as u32)
+  }
+
+
+  /// Having found opening `<<` - parse the following binary or bitstring:
+  /// `<<>>`, `<<"abc">>`, `<<1,2,3>>`, `<<1,2,3:4>>`, `<<"ä"/utf8, 1:16/little>>`
   fn binary_literal(&mut self) -> Result<SpannedToken, LexicalError> {
     self.consume_expect('<')?; // skip the second < in <<
-    self.consume_expect('\"')?; // skip the "
+    let start = self.token_start;
+    let mut writer = BitWriter::new();
+
+    self.skip_whitespace();
+    if !self.is_char_ahead('>') {
+      loop {
+        self.skip_whitespace();
+        self.binary_segment(&mut writer)?;
+        self.skip_whitespace();
+        match self.consume() {
+          Some((_, ',')) => continue,
+          Some((_, '>')) => break,
+          Some((_, ch)) => return self.err(LexicalError::Unexpected(ch)),
+          None => return self.err(LexicalError::UnexpectedEndOfFile),
+        }
+      }
+    } else {
+      self.consume();
+    }
+    self.consume_expect('>')?;
 
-    let mut out_str = String::new();
-    out_str.reserve(32);
+    let bin = FTerm::Binary(writer.data, writer.bit_size);
+    Ok((start, Token::BinaryLiteral(bin), self.position))
+  }
 
-    let start = self.current_index;
 
-    while let Some((pos, ch)) = self.consume() {
-      match ch {
-        '\\' => out_str.push(self.escape_code()?),
-        '"' => {
-          self.consume_expect('>')?;
-          self.consume_expect('>')?;
-          return Ok((start, Token::BinaryLiteral(Vec::from(out_str)), pos))
-        },
-        ch => out_str.push(ch),
-      } // match my_next
-    } // while let some
+  /// Read one `Value:Size/Type-Specifiers` segment of a binary literal
+  fn binary_segment(&mut self, writer: &mut BitWriter) -> Result<(), LexicalError> {
+    let value_tok = match self.consume() {
+      Some((_, '"')) => self.string_literal()?,
+      Some((_, '$')) => self.char_literal()?,
+      Some((_, ch)) if ch.is_digit(10) => self.numeric_literal(ch)?,
+      Some((_, '-')) if self.is_digit_ahead() => self.numeric_literal('-')?,
+      Some((_, ch)) => return self.err(LexicalError::Unexpected(ch)),
+      None => return self.err(LexicalError::UnexpectedEndOfFile),
+    };
+    let value = match value_tok.1 {
+      Token::StringLiteral(s) => SegValue::Str(s),
+      Token::IntLiteral(i) => SegValue::Int(BigInt::from(i)),
+      Token::BigIntLiteral(i) => SegValue::Int(i),
+      Token::FloatLiteral(f) => SegValue::Float(f),
+      _ => return self.err(LexicalError::BadBinary),
+    };
+
+    let mut spec = SegSpec::new();
+    if self.is_char_ahead(':') {
+      self.consume();
+      spec.size = Some(self.decimal()? as usize);
+    }
+    if self.is_char_ahead('/') {
+      // Type specifiers separated by -, e.g. /integer-little-unit:8
+      loop {
+        self.consume();
+        let mut word = String::new();
+        while let Some((_, ch)) = self.look_ahead() {
+          if !is_atom_char(ch) { break }
+          word.push(ch);
+          self.consume();
+        }
+        if word == "unit" {
+          self.consume_expect(':')?;
+          spec.unit = Some(self.decimal()? as usize);
+        } else if !spec.apply(word.as_str()) {
+          return self.err(LexicalError::BadBinary)
+        }
+        if !self.is_char_ahead('-') { break }
+      }
+    }
+    writer.push_segment(value, &spec)
+  }
 
-    Err(LexicalError::UnterminatedStringLiteral)  }
 
+  fn skip_whitespace(&mut self) {
+    while let Some((_, ch)) = self.look_ahead() {
+      if !is_whitespace(ch) { break }
+      self.consume();
+    }
+  }
 
+
+  /// Having consumed the first digit (or a minus before a digit), read the
+  /// rest of an integer or a float: `123`, `-5`, `1.5`, `2.0e-3`, `1.0E+10`,
+  /// or a based integer `16#FF`.
+  /// A float must have digits after the dot, otherwise the dot is left in the
+  /// input as the term terminator.
   fn numeric_literal(&mut self, first: char) -> Result<SpannedToken, LexicalError> {
     let mut out_str = String::new();
     out_str.reserve(10);
     out_str.push(first);
-    let start = self.current_index;
+    let start = self.token_start;
+
+    self.digits(&mut out_str);
+
+    if self.is_char_ahead('#') {
+      self.consume();
+      let tok = self.radix_literal(&out_str)?;
+      return Ok((start, tok, self.position))
+    }
+
+    let mut is_float = false;
+    if self.is_char_ahead('.') {
+      self.consume();
+      if self.is_digit_ahead() {
+        is_float = true;
+        out_str.push('.');
+        self.digits(&mut out_str);
+        self.exponent(&mut out_str)?;
+      } else {
+        self.un_consume(); // the dot belongs to the next token
+      }
+    }
 
-    while let Some((pos, ch)) = self.consume() {
-      if is_atom_char(ch) { out_str.push(ch); }
-      else { break; }
+    let tok = if is_float {
+      match f64::from_str(out_str.as_str()) {
+        Ok(val) => Token::FloatLiteral(val),
+        Err(_) => return self.err(LexicalError::BadNumber),
+      }
+    } else {
+      // Fall back to a bignum if the value does not fit into 64 bits
+      match i64::from_str(out_str.as_str()) {
+        Ok(val) => Token::IntLiteral(val),
+        Err(_) => match BigInt::from_str(out_str.as_str()) {
+          Ok(val) => Token::BigIntLiteral(val),
+          Err(_) => return self.err(LexicalError::BadNumber),
+        },
+      }
     };
-    self.un_consume();
-    let val = i64::from_str(out_str.as_str()).unwrap();
-    Ok((start, Token::IntLiteral(val), self.current_index))
+    Ok((start, tok, self.position))
+  }
+
+
+  /// Having consumed `Base#`, read the digits of a based integer such as
+  /// `16#ff`, `2#1010` or `-36#Zz`. Base is in range 2..36.
+  fn radix_literal(&mut self, base_str: &str) -> Result<Token, LexicalError> {
+    let negative = base_str.starts_with('-');
+    let radix = match u32::from_str(base_str.trim_start_matches('-')) {
+      Ok(r) if r >= 2 && r <= 36 => r,
+      _ => return self.err(LexicalError::BadNumber),
+    };
+
+    let mut out_str = String::new();
+    if negative { out_str.push('-') }
+    while let Some((_, ch)) = self.look_ahead() {
+      if ch.is_digit(radix) {
+        out_str.push(ch);
+      } else if ch != '_' {
+        break;
+      }
+      self.consume();
+    }
+    if out_str.is_empty() || out_str == "-" {
+      return self.err(LexicalError::BadNumber)
+    }
+
+    match i64::from_str_radix(out_str.as_str(), radix) {
+      Ok(val) => Ok(Token::IntLiteral(val)),
+      Err(_) => match BigInt::parse_bytes(out_str.as_bytes(), radix) {
+        Some(val) => Ok(Token::BigIntLiteral(val)),
+        None => self.err(LexicalError::BadNumber),
+      },
+    }
+  }
+
+
+  /// Having consumed `$`, read a character literal `$a` or `$\n`, which is
+  /// an integer equal to the character code.
+  fn char_literal(&mut self) -> Result<SpannedToken, LexicalError> {
+    let start = self.token_start;
+    let ch = match self.consume() {
+      Some((_, '\\')) => self.escape_code()?,
+      Some((_, ch)) => ch,
+      None => return self.err(LexicalError::UnexpectedEndOfFile),
+    };
+    Ok((start, Token::IntLiteral(ch as i64), self.position))
+  }
+
+
+  /// Read optional exponent part of a float: `e10`, `E-3`, `e+5`.
+  fn exponent(&mut self, out_str: &mut String) -> Result<(), LexicalError> {
+    if !self.is_char_ahead('e') && !self.is_char_ahead('E') {
+      return Ok(())
+    }
+    self.consume();
+    out_str.push('e');
+    if self.is_char_ahead('-') || self.is_char_ahead('+') {
+      let (_, sign) = self.consume().unwrap();
+      out_str.push(sign);
+    }
+    if !self.is_digit_ahead() {
+      return self.err(LexicalError::BadNumber)
+    }
+    self.digits(out_str);
+    Ok(())
+  }
+
+
+  /// Consume decimal digits while they are ahead, skipping the `_` digit
+  /// separators allowed since OTP 23.
+  fn digits(&mut self, out_str: &mut String) {
+    while let Some((_, ch)) = self.look_ahead() {
+      if ch.is_digit(10) {
+        out_str.push(ch);
+      } else if ch != '_' {
+        break;
+      }
+      self.consume();
+    }
   }
 
 
   fn un_consume(&mut self) {
-    if let Some(prev) = self.past.pop() {
+    if let Some((prev, prev_pos)) = self.past.pop() {
       //println!("unconsume: {}", prev.1);
-      self.upcoming.push(prev)
+      self.upcoming.push(prev);
+      self.position = prev_pos;
     }
   }
 
@@ -181,8 +515,12 @@ impl<'input> Lexer<'input> {
             self.upcoming.push(nxt)
           }
         }
-        // Store currently fetched into `past`
-        self.past.push(curr);
+        // Store currently fetched into `past`, and step the position
+        if self.past.len() >= MAX_PAST {
+          self.past.remove(0);
+        }
+        self.past.push((curr, self.position));
+        self.position = self.position.get_next(curr.1);
         Some(curr)
       },
       None => {
@@ -204,12 +542,34 @@ impl<'input> Lexer<'input> {
           'n' => Ok('\n'),
           'r' => Ok('\r'),
           't' => Ok('\t'),
-          'e' => Ok('\x1e'), // ESC code
+          'e' => Ok('\x1b'), // ESC code
           's' => Ok(' '),
           'v' => Ok('\x0b'), // vertical tab
           'f' => Ok('\x0c'), // form feed
-          'b' => Ok('\x08'), // bell
+          'b' => Ok('\x08'), // backspace
           'd' => Ok('\x7f'), // ASCII delete
+          'x' => self.hex_escape_code(),
+          '^' => { // control character: \^a is 1, \^z is 26
+            match self.consume() {
+              Some((_, c)) => Ok(((c as u32) & 31) as u8 as char),
+              None => self.err(LexicalError::UnexpectedEndOfFile),
+            }
+          },
+          '0'..='7' => {
+            // Up to three octal digits: \0, \12, \101
+            let mut code = ch.to_digit(8).unwrap();
+            for _ in 0..2 {
+              match self.look_ahead() {
+                Some((_, d)) if d.is_digit(8) => {
+                  code = code * 8 + d.to_digit(8).unwrap();
+                  self.consume();
+                },
+                _ => break,
+              }
+            }
+            // At most \777 = 511, always a valid char
+            Ok(::std::char::from_u32(code).unwrap())
+          },
           other => self.err(LexicalError::UnexpectedEscapeCode(other)),
         }
       },
@@ -217,8 +577,38 @@ impl<'input> Lexer<'input> {
     }
   }
 
+  /// Having consumed `\x`, read either two hex digits `\x41` or a braced
+  /// sequence of hex digits `\x{1F600}`.
+  fn hex_escape_code(&mut self) -> Result<char, LexicalError> {
+    let mut hex = String::new();
+    if self.is_char_ahead('{') {
+      self.consume();
+      loop {
+        match self.consume() {
+          Some((_, '}')) => break,
+          Some((_, c)) if c.is_digit(16) => hex.push(c),
+          Some((_, c)) => return self.err(LexicalError::UnexpectedEscapeCode(c)),
+          None => return self.err(LexicalError::UnexpectedEndOfFile),
+        }
+      }
+    } else {
+      for _ in 0..2 {
+        match self.consume() {
+          Some((_, c)) if c.is_digit(16) => hex.push(c),
+          Some((_, c)) => return self.err(LexicalError::UnexpectedEscapeCode(c)),
+          None => return self.err(LexicalError::UnexpectedEndOfFile),
+        }
+      }
+    }
+    match u32::from_str_radix(hex.as_str(), 16).ok().and_then(::std::char::from_u32) {
+      Some(c) => Ok(c),
+      None => self.err(LexicalError::UnexpectedEscapeCode('x')),
+    }
+  }
+
+
   fn mk_tok(&self, t: Token) -> SpannedToken {
-    (self.current_index, t, self.current_index)
+    (self.token_start, t, self.position)
   }
 
 
@@ -251,15 +641,27 @@ impl<'input> Lexer<'input> {
 }
 
 impl<'input> Iterator for Lexer<'input> {
-  type Item = Result<SpannedToken, LexicalError>;
+  type Item = Result<SpannedToken, LexerError>;
 
   fn next(&mut self) -> Option<Self::Item> {
+    self.next_token().map(|result| {
+      result.map_err(|error| LexerError { error, position: self.token_start })
+    })
+  }
+}
+
+
+impl<'input> Lexer<'input> {
+  fn next_token(&mut self) -> Option<Result<SpannedToken, LexicalError>> {
     loop {
+      self.token_start = self.position;
       match self.consume() {
-        Some((i, ch)) => {
+        Some((_, ch)) => {
           match ch {
+            '-' if self.is_digit_ahead() => return Some(self.numeric_literal('-')),
             '-' => return Some(Ok(self.mk_tok(Token::Minus))),
             ',' => return Some(Ok(self.mk_tok(Token::Comma))),
+            '|' => return Some(Ok(self.mk_tok(Token::VerticalBar))),
             '.' => return Some(Ok(self.mk_tok(Token::Dot))),
 
             '[' => return Some(Ok(self.mk_tok(Token::LSquareBracket))),
@@ -272,18 +674,38 @@ impl<'input> Iterator for Lexer<'input> {
 
             '"' => return Some(self.string_literal()),
             '\'' => return Some(self.quoted_atom_literal()),
+            '$' => return Some(self.char_literal()),
+
+            // Maps
+            '#' if self.is_char_ahead('{') => {
+              self.consume();
+              return Some(Ok(self.mk_tok(Token::HashLCurlyBracket)))
+            },
+            '#' => return Some(self.hash_literal()),
+            '=' if self.is_char_ahead('>') => {
+              self.consume();
+              return Some(Ok(self.mk_tok(Token::MapArrow)))
+            },
 
-            '-' if self.is_digit_ahead() => return Some(self.numeric_literal('-')),
             ch if ch.is_digit(10) => return Some(self.numeric_literal(ch)),
 
             ch if is_atom_start(ch) => return Some(self.atom_literal(ch)),
 
             ch if is_whitespace(ch) => continue, // skip
 
+            '%' => {
+              let text = self.comment();
+              if self.keep_comments {
+                return Some(Ok(self.mk_tok(Token::Comment(text))))
+              }
+              continue
+            },
+
             // Binaries
             '<' if self.is_char_ahead('<') => {
               return Some(self.binary_literal())
             },
+            '<' if self.is_digit_ahead() => return Some(self.pid_literal()),
 
             ch => return Some(self.err(LexicalError::Unexpected(ch))),
           };
@@ -291,14 +713,27 @@ impl<'input> Iterator for Lexer<'input> {
         None => return None,
       } // match next
     } // loop
-  } // end next
+  } // end next_token
 }
 
+/// Lowercase letters, including latin-1 ones, as Erlang accepts them
 #[inline]
-fn is_atom_start(ch: char) -> bool { ch.is_ascii_lowercase() || ch == '_' }
+fn is_lower(ch: char) -> bool {
+  ch.is_ascii_lowercase() || (('ß'..='ÿ').contains(&ch) && ch != '÷')
+}
+
+#[inline]
+fn is_atom_start(ch: char) -> bool { is_lower(ch) || ch == '_' }
 
 #[inline]
-fn is_atom_char(ch: char) -> bool { ch.is_ascii_alphanumeric() || ch == '_' }
+fn is_atom_char(ch: char) -> bool {
+  is_lower(ch)
+    || ch.is_ascii_uppercase()
+    || (('À'..='Þ').contains(&ch) && ch != '×')
+    || ch.is_ascii_digit()
+    || ch == '_'
+    || ch == '@'
+}
 
 #[inline]
 fn is_whitespace(ch: char) -> bool { ch.is_whitespace() }
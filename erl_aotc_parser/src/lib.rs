@@ -1,88 +1,412 @@
 extern crate erl_shared;
+extern crate lalrpop_util;
+extern crate num_bigint;
+extern crate num_traits;
 
 use erl_shared::fterm::FTerm;
 use erlang_term::{DottedTermParser, TermParser};
+use error::ParseError;
+use consult::ConsultIter;
 
 pub mod erlang_term;
+pub mod consult;
+pub mod error;
 pub mod position;
-mod token;
-mod lexer;
+pub mod token;
+pub mod lexer;
+mod bitstring;
 
 
-pub fn parse(input: &str) -> FTerm {
+/// Parse one term followed by a dot: `{a, b}.`
+pub fn parse(input: &str) -> Result<FTerm, ParseError> {
   let lexr = lexer::Lexer::new(input);
-  DottedTermParser::new().parse(lexr).unwrap()
+  DottedTermParser::new().parse(lexr)
+    .map_err(|e| ParseError::from_lalrpop(input, e))
 }
 
 
-pub fn parse_nodot(input: &str) -> FTerm {
+/// Parse one term without a trailing dot: `{a, b}`
+pub fn parse_nodot(input: &str) -> Result<FTerm, ParseError> {
   let lexr = lexer::Lexer::new(input);
-  TermParser::new().parse(lexr).unwrap()
+  TermParser::new().parse(lexr)
+    .map_err(|e| ParseError::from_lalrpop(input, e))
+}
+
+
+
+/// Parse all `Term.` entries of the input, like `file:consult/1` does
+pub fn consult(input: &str) -> Result<Vec<FTerm>, ParseError> {
+  consult_iter(input).collect()
+}
+
+
+/// Parse `Term.` entries of the input one at a time, for big inputs
+pub fn consult_iter(input: &str) -> ConsultIter<'_> {
+  ConsultIter::new(input)
 }
 
 
 #[cfg(test)]
 mod tests {
-  use erl_shared::fterm::FTerm;
+  use erl_shared::fterm::{FTerm, Node};
+  use error::ErrorKind;
+  use lexer::{Lexer, LexicalError};
+  use token::Token;
+  use num_bigint::BigInt;
+  use std::str::FromStr;
 
 
   fn mk_atom(s: &str) -> FTerm {
-    FTerm::Atom(s.to_string())
+    FTerm::atom(s)
   }
 
 
   #[test]
   fn erlang_term_parser_atom() {
     // One letter atom
-    let expr = ::parse_nodot("a");
+    let expr = ::parse_nodot("a").unwrap();
     assert_eq!(expr, mk_atom("a"));
 
-    let expr = ::parse_nodot("atom");
+    let expr = ::parse_nodot("atom").unwrap();
     assert_eq!(expr, mk_atom("atom"));
   }
 
   #[test]
   fn erlang_term_parser_q_atom() {
-    let expr = ::parse_nodot("'a'");
+    let expr = ::parse_nodot("'a'").unwrap();
     assert_eq!(expr, mk_atom("a"));
 
-    let expr = ::parse_nodot("'<='");
+    let expr = ::parse_nodot("'<='").unwrap();
     assert_eq!(expr, mk_atom("<="));
 
-    let expr = ::parse_nodot("'aaa@example.com'");
+    let expr = ::parse_nodot("'aaa@example.com'").unwrap();
     assert_eq!(expr, mk_atom("aaa@example.com"));
+    assert_eq!(::parse_nodot("nonode@nohost").unwrap(), mk_atom("nonode@nohost"));
+    assert_eq!(::parse_nodot("été").unwrap(), mk_atom("été"));
+
+    let expr = ::parse_nodot("'a\\'b\\x{3B1}\\n'").unwrap();
+    assert_eq!(expr, mk_atom("a'b\u{3b1}\n"));
+    assert!(::parse_nodot("'abc").is_err());
+
+    // Atoms print back as text which parses to the same atom
+    let atoms = ["a'b", "it's\t", "\u{1}\u{7f}", "\u{3b1}", "Var", "", "a\\b", "ok",
+                 "node@host", "é", "ÿÀ_1@", "ä÷", "÷", "×"];
+    for text in &atoms {
+      let printed = format!("{}", mk_atom(text));
+      assert_eq!(::parse_nodot(&printed).unwrap(), mk_atom(text), "{}", printed);
+    }
   }
 
   #[test]
   fn erlang_term_parser_str() {
-    let expr = ::parse_nodot(r#""""#);
+    let expr = ::parse_nodot(r#""""#).unwrap();
     assert_eq!(expr, FTerm::String(String::new()));
 
-    let expr = ::parse_nodot(r#""str""#);
+    let expr = ::parse_nodot(r#""str""#).unwrap();
     assert_eq!(expr, FTerm::String("str".to_string()));
   }
 
   #[test]
   fn erlang_term_parser_escaped_str() {
-    let expr = ::parse_nodot(r#""\"""#);
+    let expr = ::parse_nodot(r#""\"""#).unwrap();
     assert_eq!(expr, FTerm::String(r#"""#.to_string()));
   }
 
   #[test]
   fn erlang_term_parser_list() {
-    let expr = ::parse_nodot("[atom]");
+    let expr = ::parse_nodot("[atom]").unwrap();
     assert_eq!(expr, FTerm::List(vec![mk_atom("atom")]));
 
-    let expr = ::parse_nodot("[atom, atom]");
+    let expr = ::parse_nodot("[atom, atom]").unwrap();
     assert_eq!(expr, FTerm::List(vec![mk_atom("atom"), mk_atom("atom")]));
   }
 
   #[test]
   fn erlang_term_parser_tuple() {
-    let expr = ::parse_nodot("{atom}");
+    let expr = ::parse_nodot("{atom}").unwrap();
     assert_eq!(expr, FTerm::Tuple(vec![mk_atom("atom")]));
 
-    let expr = ::parse_nodot("{atom, atom}");
+    let expr = ::parse_nodot("{atom, atom}").unwrap();
     assert_eq!(expr, FTerm::Tuple(vec![mk_atom("atom"), mk_atom("atom")]));
   }
+
+  #[test]
+  fn erlang_term_parser_int() {
+    assert_eq!(::parse_nodot("0").unwrap(), FTerm::Int64(0));
+    assert_eq!(::parse_nodot("12345").unwrap(), FTerm::Int64(12345));
+    assert_eq!(::parse_nodot("-42").unwrap(), FTerm::Int64(-42));
+
+    let expr = ::parse("[1,2].").unwrap();
+    assert_eq!(expr, FTerm::List(vec![FTerm::Int64(1), FTerm::Int64(2)]));
+  }
+
+  #[test]
+  fn erlang_term_parser_float() {
+    assert_eq!(::parse_nodot("1.5").unwrap(), FTerm::Float(1.5));
+    assert_eq!(::parse_nodot("-0.25").unwrap(), FTerm::Float(-0.25));
+    assert_eq!(::parse_nodot("2.0e-3").unwrap(), FTerm::Float(2.0e-3));
+    assert_eq!(::parse_nodot("1.0E+10").unwrap(), FTerm::Float(1.0e10));
+    assert_eq!(::parse_nodot("-3.5e2").unwrap(), FTerm::Float(-350.0));
+
+    // A dot after an integer terminates the term, it is not a fraction
+    assert_eq!(::parse("7.").unwrap(), FTerm::Int64(7));
+    assert_eq!(::parse("7.5.").unwrap(), FTerm::Float(7.5));
+
+    let expr = ::parse_nodot("{k_float,[],1.5}").unwrap();
+    assert_eq!(expr, FTerm::Tuple(vec![mk_atom("k_float"),
+                                       FTerm::EmptyList,
+                                       FTerm::Float(1.5)]));
+  }
+
+  #[test]
+  fn erlang_term_parser_float_roundtrip() {
+    for f in &[0.0, 1.0, -1.5, 2.0e-3, 1.0e100, -7.25e-20, 123456.789] {
+      let printed = format!("{}", FTerm::Float(*f));
+      assert_eq!(::parse_nodot(&printed).unwrap(), FTerm::Float(*f), "via {}", printed);
+    }
+  }
+
+  #[test]
+  fn erlang_term_parser_bigint() {
+    let big = "123456789012345678901234567890";
+    let expr = ::parse_nodot(big).unwrap();
+    assert_eq!(expr, FTerm::BigInt(BigInt::from_str(big).unwrap()));
+    assert_eq!(format!("{}", expr), big);
+
+    let expr = ::parse_nodot("-9223372036854775809").unwrap(); // i64::MIN - 1
+    assert_eq!(format!("{}", expr), "-9223372036854775809");
+    assert!(expr.is_int());
+
+    // Fits exactly into i64, stays small
+    assert_eq!(::parse_nodot("-9223372036854775808").unwrap(),
+               FTerm::Int64(i64::min_value()));
+
+    // Small values compare equal regardless of representation
+    assert_eq!(FTerm::BigInt(BigInt::from(5)), FTerm::Int64(5));
+    assert_eq!(FTerm::from_bigint(BigInt::from(5)), FTerm::Int64(5));
+    assert_eq!(FTerm::Int64(7).get_bigint(), BigInt::from(7));
+  }
+
+  #[test]
+  fn erlang_term_parser_radix() {
+    assert_eq!(::parse_nodot("16#FF").unwrap(), FTerm::Int64(255));
+    assert_eq!(::parse_nodot("16#ff").unwrap(), FTerm::Int64(255));
+    assert_eq!(::parse_nodot("2#1010").unwrap(), FTerm::Int64(10));
+    assert_eq!(::parse_nodot("-8#17").unwrap(), FTerm::Int64(-15));
+    assert_eq!(::parse_nodot("36#Zz").unwrap(), FTerm::Int64(35 * 36 + 35));
+    assert_eq!(::parse("16#10.").unwrap(), FTerm::Int64(16));
+
+    let expr = ::parse_nodot("16#FFFFFFFFFFFFFFFFFFFF").unwrap();
+    let big = BigInt::parse_bytes(b"FFFFFFFFFFFFFFFFFFFF", 16).unwrap();
+    assert_eq!(expr, FTerm::BigInt(big));
+  }
+
+  #[test]
+  fn erlang_term_parser_char() {
+    assert_eq!(::parse_nodot("$a").unwrap(), FTerm::Int64(97));
+    assert_eq!(::parse_nodot("$\\n").unwrap(), FTerm::Int64(10));
+    assert_eq!(::parse_nodot("$\\\\").unwrap(), FTerm::Int64(92));
+    assert_eq!(::parse_nodot("$ ").unwrap(), FTerm::Int64(32));
+    assert_eq!(::parse_nodot("$\\101").unwrap(), FTerm::Int64(65));
+    assert_eq!(::parse_nodot("$\\777").unwrap(), FTerm::Int64(511));
+    assert_eq!(::parse_nodot("$\\x41").unwrap(), FTerm::Int64(65));
+    assert_eq!(::parse_nodot("$\\x{263A}").unwrap(), FTerm::Int64(0x263A));
+    assert_eq!(::parse_nodot("$\\^c").unwrap(), FTerm::Int64(3));
+    assert_eq!(::parse_nodot("$\\e").unwrap(), FTerm::Int64(27));
+
+    let expr = ::parse_nodot("[$a,$,,$]]").unwrap();
+    assert_eq!(expr, FTerm::List(vec![FTerm::Int64(97),
+                                      FTerm::Int64(44),
+                                      FTerm::Int64(93)]));
+  }
+
+  #[test]
+  fn erlang_term_parser_map() {
+    assert_eq!(::parse_nodot("#{}").unwrap(), FTerm::Map(Vec::new()));
+
+    let expr = ::parse_nodot("#{a => 1, \"b\" => [c]}").unwrap();
+    assert_eq!(expr, FTerm::Map(vec![
+      (mk_atom("a"), FTerm::Int64(1)),
+      (FTerm::String("b".to_string()), FTerm::List(vec![mk_atom("c")])),
+    ]));
+    assert_eq!(expr.map_get(&mk_atom("a")), Some(&FTerm::Int64(1)));
+
+    // Nested maps, keys repeat, order does not matter for equality
+    let expr = ::parse_nodot("#{k=>#{x=>1},k=>#{y=>2},z=>{}}").unwrap();
+    assert_eq!(expr, ::parse_nodot("#{z => {}, k => #{y => 2}}").unwrap());
+
+    let printed = format!("{}", expr);
+    assert_eq!(printed, "#{k => #{y => 2}, z => {}}");
+    assert_eq!(::parse_nodot(&printed).unwrap(), expr);
+  }
+
+  #[test]
+  fn erlang_term_parser_improper_list() {
+    let expr = ::parse_nodot("[a | b]").unwrap();
+    assert_eq!(expr, FTerm::ImproperList(vec![mk_atom("a")],
+                                         Box::new(mk_atom("b"))));
+    assert_eq!(format!("{}", expr), "[a | b]");
+
+    let expr = ::parse_nodot("[1, 2|{x}]").unwrap();
+    assert_eq!(format!("{}", expr), "[1, 2 | {x}]");
+    assert_eq!(::parse_nodot(&format!("{}", expr)).unwrap(), expr);
+
+    // A list tail is flattened into a proper or improper list
+    assert_eq!(::parse_nodot("[a | []]").unwrap(), ::parse_nodot("[a]").unwrap());
+    assert_eq!(::parse_nodot("[a | [b, c]]").unwrap(),
+               ::parse_nodot("[a, b, c]").unwrap());
+    assert_eq!(::parse_nodot("[a | [b | c]]").unwrap(),
+               ::parse_nodot("[a, b | c]").unwrap());
+  }
+
+  #[test]
+  fn erlang_term_parser_opaque() {
+    let expr = ::parse_nodot("<0.85.0>").unwrap();
+    assert_eq!(expr, FTerm::Pid { node: Node::Index(0), id: 85, serial: 0 });
+
+    let expr = ::parse_nodot("#Ref<0.1.2.3>").unwrap();
+    assert_eq!(expr, FTerm::Reference { node: Node::Index(0), ids: vec![1, 2, 3] });
+
+    let expr = ::parse_nodot("#Port<0.5>").unwrap();
+    assert_eq!(expr, FTerm::Port { node: Node::Index(0), id: 5 });
+
+    let expr = ::parse_nodot("#Fun<lists.map.2>").unwrap();
+    assert_eq!(expr, FTerm::ExportFun { module: "lists".to_string(),
+                                        fun: "map".to_string(),
+                                        arity: 2 });
+
+    let expr = ::parse_nodot("#Fun<erl_eval.6.128620087>").unwrap();
+    assert_eq!(expr, FTerm::LocalFun { module: "erl_eval".to_string(),
+                                       index: 6,
+                                       uniq: 128620087,
+                                       env: None });
+
+    // Numbers which do not fit into u32
+    assert!(::parse_nodot("<0.4294967297.0>").is_err());
+    assert!(::parse_nodot("#Ref<0.1.4294967296>").is_err());
+    assert!(::parse_nodot("#Fun<erl_eval.6.4294967296>").is_err());
+    assert_eq!(::parse_nodot("<0.4294967295.0>").unwrap(),
+               FTerm::Pid { node: Node::Index(0), id: 4294967295, serial: 0 });
+
+    // A crash dump like fragment, prints back to the same text
+    let text = "{<0.85.0>, #Ref<0.1.2.3>, #Port<0.5>, #Fun<m.'-f/1-fun-0-'.1>}";
+    let expr = ::parse("{<0.85.0>,#Ref<0.1.2.3>,#Port<0.5>,#Fun<m.'-f/1-fun-0-'.1>}.").unwrap();
+    assert_eq!(format!("{}", expr), text);
+    assert_eq!(::parse_nodot(text).unwrap(), expr);
+  }
+
+  #[test]
+  fn erlang_term_parser_binary() {
+    assert_eq!(::parse_nodot("<<>>").unwrap(), FTerm::new_binary(vec![]));
+    assert_eq!(::parse_nodot("<<\"abc\">>").unwrap(), FTerm::new_binary(vec![97, 98, 99]));
+    assert_eq!(::parse_nodot("<<1,2,3>>").unwrap(), FTerm::new_binary(vec![1, 2, 3]));
+    assert_eq!(::parse_nodot("<< 1 , \"a\" , $b >>").unwrap(), FTerm::new_binary(vec![1, 97, 98]));
+    assert_eq!(::parse_nodot("<<-1,256>>").unwrap(), FTerm::new_binary(vec![255, 0]));
+    assert_eq!(::parse_nodot("<<1:16>>").unwrap(), FTerm::new_binary(vec![0, 1]));
+    assert_eq!(::parse_nodot("<<1:16/little>>").unwrap(), FTerm::new_binary(vec![1, 0]));
+    assert_eq!(::parse_nodot("<<1:2/unit:8>>").unwrap(), FTerm::new_binary(vec![0, 1]));
+    assert_eq!(::parse_nodot("<<\"ä\"/utf8>>").unwrap(), FTerm::new_binary(vec![0xc3, 0xa4]));
+    assert_eq!(::parse_nodot("<<\"ä\">>").unwrap(), FTerm::new_binary(vec![0xe4]));
+    assert_eq!(::parse_nodot("<<$a/utf16-little>>").unwrap(), FTerm::new_binary(vec![97, 0]));
+    assert_eq!(::parse_nodot("<<1.5/float>>").unwrap(),
+               FTerm::new_binary(vec![0x3f, 0xf8, 0, 0, 0, 0, 0, 0]));
+    assert_eq!(::parse_nodot("<<1.5:32/float>>").unwrap(), FTerm::new_binary(vec![0x3f, 0xc0, 0, 0]));
+
+    // Bitstrings
+    assert_eq!(::parse_nodot("<<5:3>>").unwrap(), FTerm::Binary(vec![0b1010_0000], 3));
+    let expr = ::parse_nodot("<<1,2,3:4>>").unwrap();
+    assert_eq!(expr, FTerm::Binary(vec![1, 2, 0b0011_0000], 20));
+    assert_eq!(format!("{}", expr), "<<1,2,3:4>>");
+    assert_eq!(::parse_nodot(&format!("{}", expr)).unwrap(), expr);
+    assert_eq!(::parse_nodot("<<5:3,-1:7,300:12>>").unwrap(),
+               FTerm::Binary(vec![0xbf, 0xc4, 0xb0], 22));
+    assert_eq!(::parse_nodot("<<-2:12,16#1ff:8>>").unwrap(),
+               FTerm::Binary(vec![0xff, 0xef, 0xf0], 20));
+    assert_eq!(::parse_nodot("<<-2:70>>").unwrap(),
+               FTerm::Binary(vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xf8], 70));
+    // Sizes too large to build
+    assert!(::parse_nodot("<<1:4000000000>>").is_err());
+    assert!(::parse_nodot("<<1:4294967295/unit:256>>").is_err());
+
+    let expr = ::parse_nodot("{k_literal,[],<<\"abc\">>}").unwrap();
+    assert_eq!(format!("{}", expr), "{k_literal, [], <<97,98,99>>}");
+  }
+
+  #[test]
+  fn erlang_term_parser_error_position() {
+    let err = ::parse_nodot("{a,\n  b]").unwrap_err();
+    assert_eq!((err.position.line, err.position.column), (2, 4));
+    match err.kind {
+      ErrorKind::UnexpectedToken { token: Token::RSquareBracket, .. } => {},
+      other => panic!("Unexpected error kind {:?}", other),
+    }
+    assert_eq!(err.excerpt, "  b]\n   ^");
+    assert!(format!("{}", err).starts_with("2:4: unexpected RSquareBracket"));
+
+    let err = ::parse_nodot("[a, b, ?]").unwrap_err();
+    assert_eq!((err.position.line, err.position.column), (1, 8));
+    match err.kind {
+      ErrorKind::Lexical(LexicalError::Unexpected('?')) => {},
+      other => panic!("Unexpected error kind {:?}", other),
+    }
+
+    let err = ::parse("{a, b}").unwrap_err();
+    match err.kind {
+      ErrorKind::UnexpectedEndOfFile { .. } => {},
+      other => panic!("Unexpected error kind {:?}", other),
+    }
+    assert_eq!((err.position.line, err.position.column), (1, 7));
+  }
+
+  #[test]
+  fn erlang_term_parser_comment() {
+    let input = "% Header comment\n\
+                 {config, % trailing\n\
+                 %% full line\n\
+                 [a, \"%not a comment\"]}. % after the dot";
+    let expr = ::parse(input).unwrap();
+    assert_eq!(expr, FTerm::Tuple(vec![
+      mk_atom("config"),
+      FTerm::List(vec![mk_atom("a"), FTerm::String("%not a comment".to_string())]),
+    ]));
+
+    let comments: Vec<Token> = Lexer::with_comments(input)
+      .map(|t| t.unwrap().1)
+      .filter(|t| match t { Token::Comment(_) => true, _ => false })
+      .collect();
+    assert_eq!(comments, vec![Token::Comment(" Header comment".to_string()),
+                              Token::Comment(" trailing".to_string()),
+                              Token::Comment("% full line".to_string()),
+                              Token::Comment(" after the dot".to_string())]);
+  }
+
+  #[test]
+  fn erlang_term_parser_consult() {
+    let input = "%% rebar.config\n\
+                 {erl_opts, [debug_info]}.\n\
+                 {deps, []}.\n\
+                 {vsn, 1.5}. {pid, <0.1.2>}.\n";
+    let terms = ::consult(input).unwrap();
+    assert_eq!(terms.len(), 4);
+    assert_eq!(terms[1], FTerm::Tuple(vec![mk_atom("deps"), FTerm::EmptyList]));
+    assert_eq!(terms[2], FTerm::Tuple(vec![mk_atom("vsn"), FTerm::Float(1.5)]));
+
+    assert_eq!(::consult("").unwrap(), vec![]);
+    assert_eq!(::consult("  % only a comment\n").unwrap(), vec![]);
+
+    // The iterator gives the terms before an error, then the error
+    let mut it = ::consult_iter("a.\nb.\n{c.\nd.");
+    assert_eq!(it.next().unwrap().unwrap(), mk_atom("a"));
+    assert_eq!(it.next().unwrap().unwrap(), mk_atom("b"));
+    let err = it.next().unwrap().unwrap_err();
+    assert_eq!(err.position.line, 3);
+    assert!(it.next().is_none());
+
+    // Missing dot after the last term
+    let err = ::consult("a.\nb").unwrap_err();
+    match err.kind {
+      ErrorKind::UnexpectedEndOfFile { .. } => {},
+      other => panic!("Unexpected error kind {:?}", other),
+    }
+  }
 }
@@ -1,19 +1,43 @@
-// TODO: Make this line:column, just line is helpful already
-#[derive(Clone, Copy)]
+use std::fmt;
+
+/// Location in the input text. Lines and columns start from 1, column counts
+/// characters, offset is in bytes from the start of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Position {
-  line: usize,
+  pub offset: usize,
+  pub line: usize,
+  pub column: usize,
 }
 
 impl Position {
   pub fn new() -> Position {
-    Position { line: 1 }
+    Position { offset: 0, line: 1, column: 1 }
   }
 
 
   pub fn get_next(self, ch: char) -> Position {
+    let offset = self.offset + ch.len_utf8();
     match ch {
-      '\n' => Position { line: self.line + 1 },
-      _ => self,
+      '\n' => Position { offset, line: self.line + 1, column: 1 },
+      _ => Position { offset, column: self.column + 1, ..self },
     }
   }
+
+
+  /// Find position of the end of the input
+  pub fn end_of(input: &str) -> Position {
+    input.chars().fold(Position::new(), |pos, ch| pos.get_next(ch))
+  }
+}
+
+
+impl Default for Position {
+  fn default() -> Position { Position::new() }
+}
+
+
+impl fmt::Display for Position {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}", self.line, self.column)
+  }
 }
@@ -1,11 +1,20 @@
+use erl_shared::fterm::FTerm;
+use num_bigint::BigInt;
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Token {
   StringLiteral(String),
   AtomLiteral(String),
-  BinaryLiteral(Vec<u8>),
+  BinaryLiteral(FTerm), // <<...>>, a binary or a bitstring
   IntLiteral(i64),
+  BigIntLiteral(BigInt),
   FloatLiteral(f64),
-  Comment,
+  // Opaque values as printed by ~p, carry a ready FTerm
+  PidLiteral(FTerm), // <0.85.0>
+  PortLiteral(FTerm), // #Port<0.5>
+  RefLiteral(FTerm), // #Ref<0.1.2.3>
+  FunLiteral(FTerm), // #Fun<m.f.1> or #Fun<m.index.uniq>
+  Comment(String), // only produced if the lexer keeps comments
 
   Comma,
   Dot,
@@ -16,4 +25,7 @@ pub enum Token {
   RSquareBracket,
   RParen,
   Minus,
+  VerticalBar, // | separates list head and tail
+  HashLCurlyBracket, // #{ opens a map
+  MapArrow, // =>
 }
@@ -0,0 +1,237 @@
+//! Interned atoms. Atom text is stored once per process in a global table and
+//! an `Atom` is a small copyable reference to it, so reading the text needs no
+//! lock. Only creating an atom looks it up in the table. Atoms have no stable
+//! numbers in this process; use `AtomTable` to number the atoms of a compiled
+//! module.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+
+/// Each text is interned once, so two atoms are equal when they point to the
+/// same text.
+#[derive(Clone, Copy)]
+pub struct Atom(&'static str);
+
+
+// Interned text is never freed, same as atoms in the Erlang VM
+static INTERNER: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+
+impl Atom {
+  /// Find or create the atom with this text.
+  pub fn new(text: &str) -> Atom {
+    let mut interner = INTERNER.lock().unwrap();
+    if let Some(name) = interner.get(text) {
+      return Atom(name)
+    }
+    let name: &'static str = Box::leak(text.to_string().into_boxed_str());
+    interner.insert(name);
+    Atom(name)
+  }
+
+
+  #[inline]
+  pub fn as_str(&self) -> &'static str {
+    self.0
+  }
+}
+
+
+impl PartialEq for Atom {
+  #[inline]
+  fn eq(&self, other: &Atom) -> bool {
+    ::std::ptr::eq(self.0, other.0)
+  }
+}
+
+
+impl Eq for Atom {}
+
+
+impl Hash for Atom {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    (self.0.as_ptr() as usize).hash(state)
+  }
+}
+
+
+impl From<&str> for Atom {
+  fn from(text: &str) -> Atom { Atom::new(text) }
+}
+
+
+impl From<String> for Atom {
+  fn from(text: String) -> Atom { Atom::new(&text) }
+}
+
+
+impl PartialEq<str> for Atom {
+  fn eq(&self, other: &str) -> bool { self.as_str() == other }
+}
+
+
+impl PartialEq<&str> for Atom {
+  fn eq(&self, other: &&str) -> bool { self.as_str() == *other }
+}
+
+
+/// Atoms sort by their text, as in Erlang
+impl Ord for Atom {
+  fn cmp(&self, other: &Atom) -> Ordering {
+    if self == other {
+      return Ordering::Equal
+    }
+    self.0.cmp(other.0)
+  }
+}
+
+
+impl PartialOrd for Atom {
+  fn partial_cmp(&self, other: &Atom) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+
+/// Prints the text as is, use `FTerm` to print it quoted when needed
+impl fmt::Display for Atom {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+
+impl fmt::Debug for Atom {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:?}", self.as_str())
+  }
+}
+
+
+/// Atom text longer than the 255 bytes an atom table entry can hold
+#[derive(Debug, PartialEq)]
+pub struct AtomTooLong(pub Atom);
+
+
+/// Atoms used by one module, numbered from 0 in the order they were added.
+/// The same input always produces the same table, which is emitted for the
+/// runtime to map the numbers to its own atoms when the module is loaded.
+#[derive(Clone, Debug, Default)]
+pub struct AtomTable {
+  atoms: Vec<Atom>,
+  index: HashMap<Atom, u32>,
+}
+
+
+impl AtomTable {
+  pub fn new() -> AtomTable {
+    AtomTable::default()
+  }
+
+
+  /// Add the atom if not yet in the table, return its number.
+  pub fn add(&mut self, atom: Atom) -> u32 {
+    if let Some(i) = self.index.get(&atom) {
+      return *i
+    }
+    let i = self.atoms.len() as u32;
+    self.atoms.push(atom);
+    self.index.insert(atom, i);
+    i
+  }
+
+
+  /// Intern the text and add the atom to the table.
+  pub fn intern(&mut self, text: &str) -> Atom {
+    let atom = Atom::new(text);
+    self.add(atom);
+    atom
+  }
+
+
+  pub fn index_of(&self, atom: Atom) -> Option<u32> {
+    self.index.get(&atom).cloned()
+  }
+
+
+  pub fn get(&self, index: u32) -> Option<Atom> {
+    self.atoms.get(index as usize).cloned()
+  }
+
+
+  pub fn len(&self) -> usize { self.atoms.len() }
+
+
+  pub fn is_empty(&self) -> bool { self.atoms.is_empty() }
+
+
+  /// Atoms in the order of their numbers.
+  pub fn iter<'a>(&'a self) -> ::std::slice::Iter<'a, Atom> {
+    self.atoms.iter()
+  }
+
+
+  /// Serialize as in the `AtU8` chunk of a BEAM file: big-endian u32 count,
+  /// then each atom as a length byte and UTF-8 text. Atoms longer than 255
+  /// bytes are not valid in Erlang and fail the whole table.
+  pub fn to_bytes(&self) -> Result<Vec<u8>, AtomTooLong> {
+    let n = self.atoms.len() as u32;
+    let mut out = vec![(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8];
+    for a in &self.atoms {
+      let text = a.as_str();
+      if text.len() > 255 {
+        return Err(AtomTooLong(*a))
+      }
+      out.push(text.len() as u8);
+      out.extend(text.as_bytes());
+    }
+    Ok(out)
+  }
+}
+
+
+/// Atoms are written as their text, indexes are only valid in this process
+#[cfg(feature = "serialize")]
+mod serialize {
+  use super::{Atom, AtomTable};
+  use serde::de::{Deserialize, Deserializer};
+  use serde::ser::{Serialize, Serializer};
+
+
+  impl Serialize for Atom {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+      s.serialize_str(self.as_str())
+    }
+  }
+
+
+  impl<'de> Deserialize<'de> for Atom {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Atom, D::Error> {
+      let text = String::deserialize(d)?;
+      Ok(Atom::new(&text))
+    }
+  }
+
+
+  /// A list of atom texts in the order of their numbers
+  impl Serialize for AtomTable {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+      self.atoms.serialize(s)
+    }
+  }
+
+
+  impl<'de> Deserialize<'de> for AtomTable {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<AtomTable, D::Error> {
+      let mut table = AtomTable::new();
+      for a in Vec::<Atom>::deserialize(d)? {
+        table.add(a);
+      }
+      Ok(table)
+    }
+  }
+}
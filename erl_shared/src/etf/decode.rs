@@ -0,0 +1,318 @@
+//! Decodes External Term Format (output of `term_to_binary`) into FTerm.
+
+use etf::*;
+use flate2::read::ZlibDecoder;
+use fterm::{FTerm, FunEnv, Node};
+use num_bigint::{BigInt, Sign};
+use std::io::Read;
+
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+  UnexpectedEnd,
+  BadVersion(u8),
+  UnknownTag(u8),
+  /// A term of one kind was expected inside another term, like an atom for
+  /// the node name of a pid
+  Expected(&'static str),
+  BadUtf8,
+  BadFloat,
+  BadCompressed,
+  /// A number outside the range its field allows, like an export arity
+  /// above 255
+  OutOfRange(&'static str),
+  /// Bytes left after the term was decoded
+  TrailingBytes(usize),
+}
+
+
+/// Decode a complete `term_to_binary` output, starting with the version byte.
+/// Compressed terms are unpacked.
+pub fn decode(data: &[u8]) -> Result<FTerm, DecodeError> {
+  let mut r = Reader { data, pos: 0 };
+  let version = r.u8()?;
+  if version != VERSION {
+    return Err(DecodeError::BadVersion(version))
+  }
+
+  if r.peek()? == COMPRESSED {
+    r.u8()?;
+    let size = r.u32()? as usize;
+    // Do not trust the size for allocation, only unpack one byte past it
+    let mut unpacked = Vec::new();
+    let mut zr = ZlibDecoder::new(r.rest()).take(size as u64 + 1);
+    if zr.read_to_end(&mut unpacked).is_err()
+        || unpacked.len() != size {
+      return Err(DecodeError::BadCompressed)
+    }
+    let mut r2 = Reader { data: &unpacked, pos: 0 };
+    return r2.term_to_end()
+  }
+  r.term_to_end()
+}
+
+
+struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+
+impl<'a> Reader<'a> {
+  fn term_to_end(&mut self) -> Result<FTerm, DecodeError> {
+    let t = self.term()?;
+    match self.data.len() - self.pos {
+      0 => Ok(t),
+      n => Err(DecodeError::TrailingBytes(n)),
+    }
+  }
+
+
+  fn peek(&self) -> Result<u8, DecodeError> {
+    match self.data.get(self.pos) {
+      Some(b) => Ok(*b),
+      None => Err(DecodeError::UnexpectedEnd),
+    }
+  }
+
+
+  fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+    if self.data.len() - self.pos < n {
+      return Err(DecodeError::UnexpectedEnd)
+    }
+    let b = &self.data[self.pos..self.pos + n];
+    self.pos += n;
+    Ok(b)
+  }
+
+
+  /// Capacity to reserve for `n` items which take at least a byte each, the
+  /// count read from the input may be wrong
+  fn capacity(&self, n: usize) -> usize {
+    n.min(self.data.len() - self.pos)
+  }
+
+
+  fn rest(&mut self) -> &'a [u8] {
+    let b = &self.data[self.pos..];
+    self.pos = self.data.len();
+    b
+  }
+
+
+  fn u8(&mut self) -> Result<u8, DecodeError> {
+    let b = self.peek()?;
+    self.pos += 1;
+    Ok(b)
+  }
+
+
+  fn u16(&mut self) -> Result<u16, DecodeError> {
+    let b = self.bytes(2)?;
+    Ok((b[0] as u16) << 8 | b[1] as u16)
+  }
+
+
+  fn u32(&mut self) -> Result<u32, DecodeError> {
+    let b = self.bytes(4)?;
+    Ok(b.iter().fold(0u32, |acc, x| acc << 8 | *x as u32))
+  }
+
+
+  fn u64(&mut self) -> Result<u64, DecodeError> {
+    let b = self.bytes(8)?;
+    Ok(b.iter().fold(0u64, |acc, x| acc << 8 | *x as u64))
+  }
+
+
+  fn term(&mut self) -> Result<FTerm, DecodeError> {
+    let tag = self.u8()?;
+    match tag {
+      SMALL_INTEGER_EXT => Ok(FTerm::Int64(self.u8()? as i64)),
+      INTEGER_EXT => Ok(FTerm::Int64(self.u32()? as i32 as i64)),
+      SMALL_BIG_EXT => {
+        let n = self.u8()? as usize;
+        self.bignum(n)
+      },
+      LARGE_BIG_EXT => {
+        let n = self.u32()? as usize;
+        self.bignum(n)
+      },
+      NEW_FLOAT_EXT => Ok(FTerm::Float(f64::from_bits(self.u64()?))),
+      FLOAT_EXT => {
+        // Old format: float printed with "%.20e" in a 31 byte buffer
+        let b = self.bytes(31)?;
+        let text: String = b.iter().take_while(|c| **c != 0).map(|c| *c as char).collect();
+        match text.trim().parse::<f64>() {
+          Ok(f) => Ok(FTerm::Float(f)),
+          Err(_) => Err(DecodeError::BadFloat),
+        }
+      },
+
+      ATOM_EXT | SMALL_ATOM_EXT | ATOM_UTF8_EXT | SMALL_ATOM_UTF8_EXT => {
+        self.pos -= 1;
+        Ok(FTerm::atom(&self.atom()?))
+      },
+      ATOM_CACHE_REF => Err(DecodeError::UnknownTag(tag)),
+
+      SMALL_TUPLE_EXT => {
+        let arity = self.u8()? as usize;
+        self.tuple(arity)
+      },
+      LARGE_TUPLE_EXT => {
+        let arity = self.u32()? as usize;
+        self.tuple(arity)
+      },
+      NIL_EXT => Ok(FTerm::EmptyList),
+      STRING_EXT => {
+        // List of bytes, same as a "string" in the text form
+        let len = self.u16()? as usize;
+        let b = self.bytes(len)?;
+        Ok(FTerm::String(b.iter().map(|c| *c as char).collect()))
+      },
+      LIST_EXT => {
+        let len = self.u32()? as usize;
+        let mut elements = Vec::with_capacity(self.capacity(len));
+        for _ in 0..len {
+          elements.push(self.term()?);
+        }
+        let tail = self.term()?;
+        Ok(FTerm::new_improper_list(elements, tail))
+      },
+      MAP_EXT => {
+        let size = self.u32()? as usize;
+        let mut pairs = Vec::with_capacity(self.capacity(size));
+        for _ in 0..size {
+          let k = self.term()?;
+          let v = self.term()?;
+          pairs.push((k, v));
+        }
+        Ok(FTerm::new_map(pairs))
+      },
+
+      BINARY_EXT => {
+        let len = self.u32()? as usize;
+        Ok(FTerm::new_binary(self.bytes(len)?.to_vec()))
+      },
+      BIT_BINARY_EXT => {
+        let len = self.u32()? as usize;
+        let last_bits = self.u8()? as usize;
+        if len == 0 && last_bits != 0 || len != 0 && !(1..=8).contains(&last_bits) {
+          return Err(DecodeError::OutOfRange("bits"))
+        }
+        let data = self.bytes(len)?.to_vec();
+        let bit_size = if len == 0 { 0 } else { (len - 1) * 8 + last_bits };
+        Ok(FTerm::Binary(data, bit_size))
+      },
+
+      PID_EXT | NEW_PID_EXT => {
+        let name = self.atom()?;
+        let id = self.u32()?;
+        let serial = self.u32()?;
+        let creation = self.creation(tag == NEW_PID_EXT)?;
+        Ok(FTerm::Pid { node: Node::Name(name, creation), id, serial })
+      },
+      PORT_EXT | NEW_PORT_EXT | V4_PORT_EXT => {
+        let name = self.atom()?;
+        let id = if tag == V4_PORT_EXT { self.u64()? } else { self.u32()? as u64 };
+        let creation = self.creation(tag != PORT_EXT)?;
+        Ok(FTerm::Port { node: Node::Name(name, creation), id })
+      },
+      REFERENCE_EXT => {
+        let name = self.atom()?;
+        let id = self.u32()?;
+        let creation = self.creation(false)?;
+        Ok(FTerm::Reference { node: Node::Name(name, creation), ids: vec![id] })
+      },
+      NEW_REFERENCE_EXT | NEWER_REFERENCE_EXT => {
+        let len = self.u16()? as usize;
+        let name = self.atom()?;
+        let creation = self.creation(tag == NEWER_REFERENCE_EXT)?;
+        let mut ids = Vec::with_capacity(self.capacity(len));
+        for _ in 0..len {
+          ids.push(self.u32()?);
+        }
+        Ok(FTerm::Reference { node: Node::Name(name, creation), ids })
+      },
+
+      EXPORT_EXT => {
+        let module = self.atom()?;
+        let fun = self.atom()?;
+        let arity = match self.term()? {
+          FTerm::Int64(a) if (0..256).contains(&a) => a as u32,
+          FTerm::Int64(_) => return Err(DecodeError::OutOfRange("arity")),
+          _ => return Err(DecodeError::Expected("arity")),
+        };
+        Ok(FTerm::ExportFun { module, fun, arity })
+      },
+      NEW_FUN_EXT => {
+        let _size = self.u32()?;
+        let arity = self.u8()? as u32;
+        let mut md5 = [0u8; 16];
+        md5.copy_from_slice(self.bytes(16)?);
+        let index = self.u32()?;
+        let num_free = self.u32()? as usize;
+        let module = self.atom()?;
+        let _old_index = self.term()?;
+        let uniq = match self.term()? {
+          FTerm::Int64(u) => u as u32,
+          _ => return Err(DecodeError::Expected("old uniq")),
+        };
+        let pid = self.term()?;
+        let mut free_vars = Vec::with_capacity(self.capacity(num_free));
+        for _ in 0..num_free {
+          free_vars.push(self.term()?);
+        }
+        let env = FunEnv { arity, md5, pid, free_vars };
+        Ok(FTerm::LocalFun { module, index, uniq, env: Some(Box::new(env)) })
+      },
+
+      other => Err(DecodeError::UnknownTag(other)),
+    }
+  }
+
+
+  /// Read any atom encoding, return the text
+  fn atom(&mut self) -> Result<String, DecodeError> {
+    let tag = self.u8()?;
+    let len = match tag {
+      ATOM_EXT | ATOM_UTF8_EXT => self.u16()? as usize,
+      SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => self.u8()? as usize,
+      _ => return Err(DecodeError::Expected("atom")),
+    };
+    let b = self.bytes(len)?;
+    match tag {
+      ATOM_UTF8_EXT | SMALL_ATOM_UTF8_EXT => match ::std::str::from_utf8(b) {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => Err(DecodeError::BadUtf8),
+      },
+      _ => Ok(b.iter().map(|c| *c as char).collect()), // Latin-1
+    }
+  }
+
+
+  /// Creation is one byte in older encodings and 4 bytes in newer
+  fn creation(&mut self, wide: bool) -> Result<u32, DecodeError> {
+    if wide { self.u32() } else { Ok(self.u8()? as u32) }
+  }
+
+
+  fn tuple(&mut self, arity: usize) -> Result<FTerm, DecodeError> {
+    if arity == 0 {
+      return Ok(FTerm::EmptyTuple)
+    }
+    let mut elements = Vec::with_capacity(self.capacity(arity));
+    for _ in 0..arity {
+      elements.push(self.term()?);
+    }
+    Ok(FTerm::Tuple(elements))
+  }
+
+
+  /// Sign byte followed by n digit bytes, little-endian
+  fn bignum(&mut self, n: usize) -> Result<FTerm, DecodeError> {
+    let sign = if self.u8()? == 0 { Sign::Plus } else { Sign::Minus };
+    let digits = self.bytes(n)?;
+    Ok(FTerm::from_bigint(BigInt::from_bytes_le(sign, digits)))
+  }
+}
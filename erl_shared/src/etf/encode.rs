@@ -0,0 +1,334 @@
+//! Encodes FTerm into External Term Format, accepted by `binary_to_term`.
+
+use etf::*;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use fterm::{FTerm, Node};
+use num_bigint::{BigInt, Sign};
+use num_traits::ToPrimitive;
+use std::io::Write;
+
+
+#[derive(Debug, PartialEq)]
+pub enum EncodeError {
+  /// Atom text is longer than 255 characters
+  AtomTooLong,
+  /// Anonymous fun parsed from text has no environment (md5, free vars),
+  /// which the external format requires
+  MissingFunEnv,
+  /// A count (list length, tuple arity, bignum size) exceeds 32 bits
+  TooLarge,
+}
+
+
+/// Name used for `Node::Index` nodes, which do not carry a name. The same
+/// name is used by a VM which is not distributed.
+pub const LOCAL_NODE_NAME: &str = "nonode@nohost";
+
+
+/// Encode a term as `term_to_binary/1` would, starting with the version byte.
+pub fn encode(term: &FTerm) -> Result<Vec<u8>, EncodeError> {
+  let mut w = Writer { out: vec![VERSION] };
+  w.term(term)?;
+  Ok(w.out)
+}
+
+
+/// Encode a term as `term_to_binary(T, [{compressed, Level}])` would, level
+/// is 0..9. Same as `encode` if compression does not make the result smaller.
+pub fn encode_compressed(term: &FTerm, level: u32) -> Result<Vec<u8>, EncodeError> {
+  let plain = encode(term)?;
+  let body = &plain[1..];
+  if body.len() > u32::MAX as usize {
+    return Err(EncodeError::TooLarge)
+  }
+
+  let mut z = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+  // Writing into a Vec does not fail
+  z.write_all(body).unwrap();
+  let packed = z.finish().unwrap();
+
+  if packed.len() + 5 >= body.len() {
+    return Ok(plain)
+  }
+  let mut out = Vec::with_capacity(packed.len() + 6);
+  out.push(VERSION);
+  out.push(COMPRESSED);
+  push_u32(&mut out, body.len() as u32);
+  out.extend(packed);
+  Ok(out)
+}
+
+
+struct Writer {
+  out: Vec<u8>,
+}
+
+
+fn push_u32(out: &mut Vec<u8>, v: u32) {
+  out.extend(&[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]);
+}
+
+
+fn count(n: usize) -> Result<u32, EncodeError> {
+  if n > u32::MAX as usize {
+    return Err(EncodeError::TooLarge)
+  }
+  Ok(n as u32)
+}
+
+
+impl Writer {
+  fn u8(&mut self, v: u8) { self.out.push(v) }
+
+
+  fn u16(&mut self, v: u16) {
+    self.out.extend(&[(v >> 8) as u8, v as u8]);
+  }
+
+
+  fn u32(&mut self, v: u32) { push_u32(&mut self.out, v) }
+
+
+  fn u64(&mut self, v: u64) {
+    self.u32((v >> 32) as u32);
+    self.u32(v as u32);
+  }
+
+
+  fn term(&mut self, term: &FTerm) -> Result<(), EncodeError> {
+    match term {
+      FTerm::Int64(i) => self.int(*i),
+      FTerm::BigInt(b) => self.bigint(b)?,
+      FTerm::Float(f) => {
+        self.u8(NEW_FLOAT_EXT);
+        self.u64(f.to_bits());
+      },
+      FTerm::Atom(s) => self.atom(s.as_str())?,
+
+      FTerm::EmptyTuple => {
+        self.u8(SMALL_TUPLE_EXT);
+        self.u8(0);
+      },
+      FTerm::Tuple(elements) => {
+        if elements.len() < 256 {
+          self.u8(SMALL_TUPLE_EXT);
+          self.u8(elements.len() as u8);
+        } else {
+          self.u8(LARGE_TUPLE_EXT);
+          self.u32(count(elements.len())?);
+        }
+        for e in elements {
+          self.term(e)?;
+        }
+      },
+
+      FTerm::EmptyList => self.u8(NIL_EXT),
+      FTerm::String(s) => self.string(s)?,
+      FTerm::List(elements) => self.list(elements, None)?,
+      FTerm::ImproperList(elements, tail) => self.list(elements, Some(&**tail))?,
+      FTerm::Map(pairs) => {
+        self.u8(MAP_EXT);
+        self.u32(count(pairs.len())?);
+        for (k, v) in pairs {
+          self.term(k)?;
+          self.term(v)?;
+        }
+      },
+
+      FTerm::Binary(data, bit_size) => {
+        let len = count(data.len())?;
+        let tail_bits = bit_size % 8;
+        if tail_bits == 0 {
+          self.u8(BINARY_EXT);
+          self.u32(len);
+        } else {
+          self.u8(BIT_BINARY_EXT);
+          self.u32(len);
+          self.u8(tail_bits as u8);
+        }
+        self.out.extend(data);
+      },
+
+      FTerm::Pid { node, id, serial } => {
+        self.u8(NEW_PID_EXT);
+        let creation = self.node(node)?;
+        self.u32(*id);
+        self.u32(*serial);
+        self.u32(creation);
+      },
+      FTerm::Port { node, id } => {
+        if *id > u32::MAX as u64 {
+          self.u8(V4_PORT_EXT);
+          let creation = self.node(node)?;
+          self.u64(*id);
+          self.u32(creation);
+        } else {
+          self.u8(NEW_PORT_EXT);
+          let creation = self.node(node)?;
+          self.u32(*id as u32);
+          self.u32(creation);
+        }
+      },
+      FTerm::Reference { node, ids } => {
+        if ids.len() > u16::MAX as usize {
+          return Err(EncodeError::TooLarge)
+        }
+        self.u8(NEWER_REFERENCE_EXT);
+        self.u16(ids.len() as u16);
+        let creation = self.node(node)?;
+        self.u32(creation);
+        for id in ids {
+          self.u32(*id);
+        }
+      },
+
+      FTerm::ExportFun { module, fun, arity } => {
+        self.u8(EXPORT_EXT);
+        self.atom(module)?;
+        self.atom(fun)?;
+        if *arity > 255 {
+          return Err(EncodeError::TooLarge)
+        }
+        self.int(*arity as i64);
+      },
+      FTerm::LocalFun { module, index, uniq, env } => {
+        let env = match env {
+          Some(e) => e,
+          None => return Err(EncodeError::MissingFunEnv),
+        };
+        self.u8(NEW_FUN_EXT);
+        // Size includes the size field itself, patched when the fun is done
+        let size_at = self.out.len();
+        self.u32(0);
+        if env.arity > 255 {
+          return Err(EncodeError::TooLarge)
+        }
+        self.u8(env.arity as u8);
+        self.out.extend(&env.md5);
+        self.u32(*index);
+        self.u32(count(env.free_vars.len())?);
+        self.atom(module)?;
+        self.int(*index as i64); // old index
+        self.int(*uniq as i64);
+        self.term(&env.pid)?;
+        for v in &env.free_vars {
+          self.term(v)?;
+        }
+        let size = count(self.out.len() - size_at)?;
+        for i in 0..4 {
+          self.out[size_at + i] = (size >> (24 - 8 * i)) as u8;
+        }
+      },
+    }
+    Ok(())
+  }
+
+
+  fn int(&mut self, i: i64) {
+    if (0..256).contains(&i) {
+      self.u8(SMALL_INTEGER_EXT);
+      self.u8(i as u8);
+    } else if (i32::MIN as i64..=i32::MAX as i64).contains(&i) {
+      self.u8(INTEGER_EXT);
+      self.u32(i as i32 as u32);
+    } else {
+      // Cannot fail: any i64 fits into a small bignum
+      self.bigint(&BigInt::from(i)).unwrap();
+    }
+  }
+
+
+  fn bigint(&mut self, b: &BigInt) -> Result<(), EncodeError> {
+    // A BigInt built directly may still be small, use the shortest form
+    if let Some(i) = b.to_i32() {
+      self.int(i as i64);
+      return Ok(())
+    }
+    let (sign, digits) = b.to_bytes_le();
+    if digits.len() < 256 {
+      self.u8(SMALL_BIG_EXT);
+      self.u8(digits.len() as u8);
+    } else {
+      self.u8(LARGE_BIG_EXT);
+      self.u32(count(digits.len())?);
+    }
+    self.u8(if sign == Sign::Minus { 1 } else { 0 });
+    self.out.extend(digits);
+    Ok(())
+  }
+
+
+  /// Atoms are always written as UTF-8, at most 255 characters are allowed
+  fn atom(&mut self, s: &str) -> Result<(), EncodeError> {
+    if s.chars().count() > 255 {
+      return Err(EncodeError::AtomTooLong)
+    }
+    let b = s.as_bytes();
+    if b.len() < 256 {
+      self.u8(SMALL_ATOM_UTF8_EXT);
+      self.u8(b.len() as u8);
+    } else {
+      // 255 characters are at most 1020 bytes
+      self.u8(ATOM_UTF8_EXT);
+      self.u16(b.len() as u16);
+    }
+    self.out.extend(b);
+    Ok(())
+  }
+
+
+  /// Latin-1 strings up to 65535 characters use the compact byte form,
+  /// others become a list of character codes.
+  fn string(&mut self, s: &str) -> Result<(), EncodeError> {
+    let len = s.chars().count();
+    if len == 0 {
+      self.u8(NIL_EXT);
+    } else if len <= u16::MAX as usize && s.chars().all(|c| (c as u32) < 256) {
+      self.u8(STRING_EXT);
+      self.u16(len as u16);
+      for c in s.chars() {
+        self.u8(c as u32 as u8);
+      }
+    } else {
+      self.u8(LIST_EXT);
+      self.u32(count(len)?);
+      for c in s.chars() {
+        self.int(c as i64);
+      }
+      self.u8(NIL_EXT);
+    }
+    Ok(())
+  }
+
+
+  fn list(&mut self, elements: &[FTerm], tail: Option<&FTerm>) -> Result<(), EncodeError> {
+    self.u8(LIST_EXT);
+    self.u32(count(elements.len())?);
+    for e in elements {
+      self.term(e)?;
+    }
+    match tail {
+      Some(t) => self.term(t),
+      None => {
+        self.u8(NIL_EXT);
+        Ok(())
+      },
+    }
+  }
+
+
+  /// Write the node name atom, return creation to be written after the ids
+  fn node(&mut self, node: &Node) -> Result<u32, EncodeError> {
+    match node {
+      Node::Index(_) => {
+        self.atom(LOCAL_NODE_NAME)?;
+        Ok(0)
+      },
+      Node::Name(name, creation) => {
+        self.atom(name)?;
+        Ok(*creation)
+      },
+    }
+  }
+}
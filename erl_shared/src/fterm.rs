@@ -1,26 +1,159 @@
+use atom::Atom;
+use pretty;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 use std::fmt;
 
-/// Represents Erlang values.
+/// Represents Erlang values. With the `serialize` feature a term is written
+/// as `{"type": "tuple", "value": [...]}`, big integers as decimal text.
 // #[repr(u8)]
-#[derive(PartialEq, Clone)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize",
+           serde(tag = "type", content = "value", rename_all = "snake_case"))]
 #[allow(dead_code)]
 pub enum FTerm {
-  /// Runtime atom index in the VM atom table
-  Atom(String),
+  /// Interned atom, see `atom::AtomTable` for its index in the runtime table
+  Atom(Atom),
   String(String),
   Int64(i64),
+  /// Integer which does not fit into i64, see `FTerm::from_bigint`
+  #[cfg_attr(feature = "serialize", serde(with = "bigint_text"))]
+  BigInt(BigInt),
   List(Vec<FTerm>),
   EmptyList,
+  /// List `[a, b | tail]` which does not end with `[]`, tail is never a list,
+  /// see `FTerm::new_improper_list`
+  ImproperList(Vec<FTerm>, Box<FTerm>),
   Tuple(Vec<FTerm>),
   EmptyTuple,
   Float(f64),
-  Binary(Vec<u8>),
+  /// Bytes and size in bits. For a bitstring whose size is not a multiple of
+  /// 8 the last byte is only partially used, starting from its high bits.
+  Binary(Vec<u8>, usize),
+  /// Key-value pairs sorted by key in term order, each key is unique, see
+  /// `FTerm::new_map`
+  Map(Vec<(FTerm, FTerm)>),
+  /// Process id `<0.85.0>`
+  Pid { node: Node, id: u32, serial: u32 },
+  /// Port `#Port<0.5>`
+  Port { node: Node, id: u64 },
+  /// Reference `#Ref<0.1.2.3>`
+  Reference { node: Node, ids: Vec<u32> },
+  /// External fun `fun m:f/1`, printed as `#Fun<m.f.1>`
+  ExportFun { module: String, fun: String, arity: u32 },
+  /// Anonymous fun printed as `#Fun<m.index.uniq>`, the environment is only
+  /// known if the fun was decoded from the external term format
+  LocalFun { module: String, index: u32, uniq: u32, env: Option<Box<FunEnv>> },
 }
 
+
+/// Parts of an anonymous fun which only the external term format carries
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct FunEnv {
+  pub arity: u32,
+  /// MD5 of the module code the fun belongs to
+  pub md5: [u8; 16],
+  /// Process which created the fun
+  pub pid: FTerm,
+  /// Values of captured variables
+  pub free_vars: Vec<FTerm>,
+}
+
+
+/// Node which owns a pid, port or reference. Printed terms only carry the
+/// node number as seen by the printing node (0 is the local node), while
+/// the external term format carries node name and its creation.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize",
+           serde(tag = "type", content = "value", rename_all = "snake_case"))]
+pub enum Node {
+  Index(u32),
+  Name(String, u32),
+}
+
+
+impl fmt::Display for Node {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Node::Index(i) => write!(f, "{}", i),
+      // Text form has no place for a node name, print it as the local node
+      Node::Name(_, _) => write!(f, "0"),
+    }
+  }
+}
+
+
 impl FTerm {
+  /// Create an atom term, interning the text.
+  pub fn atom(text: &str) -> FTerm {
+    FTerm::Atom(Atom::new(text))
+  }
+
+
+  /// Create an integer term, using `Int64` if the value fits, and `BigInt`
+  /// otherwise.
+  pub fn from_bigint(b: BigInt) -> FTerm {
+    match b.to_i64() {
+      Some(i) => FTerm::Int64(i),
+      None => FTerm::BigInt(b),
+    }
+  }
+
+
+  /// Create a binary from whole bytes.
+  pub fn new_binary(data: Vec<u8>) -> FTerm {
+    let bit_size = data.len() * 8;
+    FTerm::Binary(data, bit_size)
+  }
+
+
+  /// Create a list term `[elements... | tail]`. If the tail is a list itself,
+  /// its elements are appended, so the result is either a proper `List` or
+  /// an `ImproperList` whose tail is not a list.
+  pub fn new_improper_list(elements: Vec<FTerm>, tail: FTerm) -> FTerm {
+    let mut elements = elements;
+    match tail {
+      FTerm::EmptyList => {},
+      FTerm::List(v) => elements.extend(v),
+      FTerm::ImproperList(v, t) => {
+        elements.extend(v);
+        return FTerm::ImproperList(elements, t)
+      },
+      other => {
+        if elements.is_empty() { return other } // [ | X] is just X
+        return FTerm::ImproperList(elements, Box::new(other))
+      },
+    }
+    match elements.len() {
+      0 => FTerm::EmptyList,
+      _ => FTerm::List(elements),
+    }
+  }
+
+
+  /// Create a map term from key-value pairs. Keys are sorted in term order,
+  /// a repeated key overwrites the earlier value (as `#{a=>1, a=>2}`
+  /// evaluates to `#{a=>2}` in Erlang).
+  pub fn new_map(mut pairs: Vec<(FTerm, FTerm)>) -> FTerm {
+    // Stable sort, a repeated key is followed by its later values
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut out: Vec<(FTerm, FTerm)> = Vec::with_capacity(pairs.len());
+    for (k, v) in pairs {
+      match out.last_mut() {
+        Some(last) if last.0 == k => last.1 = v,
+        _ => out.push((k, v)),
+      }
+    }
+    FTerm::Map(out)
+  }
+
+
   pub fn get_atom_text(&self) -> String {
     if let FTerm::Atom(s) = self {
-      return s.clone();
+      return s.as_str().to_string();
     }
     panic!("Atom is expected, got {}", self)
   }
@@ -34,6 +167,16 @@ impl FTerm {
   }
 
 
+  /// Return any integer term (small or big) as a `BigInt`.
+  pub fn get_bigint(&self) -> BigInt {
+    match self {
+      FTerm::Int64(i) => BigInt::from(*i),
+      FTerm::BigInt(b) => b.clone(),
+      _ => panic!("Integer is expected, got {}", self),
+    }
+  }
+
+
   pub fn get_vec(&self) -> Vec<FTerm> {
     match self {
       FTerm::List(v) => v.clone(),
@@ -46,27 +189,17 @@ impl FTerm {
 
 
   pub fn is_int(&self) -> bool {
-    match self {
-      FTerm::Int64(_) => true,
-      _ => false,
-    }
+    matches!(self, FTerm::Int64(_) | FTerm::BigInt(_))
   }
 
 
   pub fn is_atom(&self) -> bool {
-    match self {
-      FTerm::Atom(_) => true,
-      _ => false,
-    }
+    matches!(self, FTerm::Atom(_))
   }
 
 
   pub fn is_tuple(&self) -> bool {
-    match self {
-      FTerm::Tuple(_) => true,
-      FTerm::EmptyTuple => true,
-      _ => false,
-    }
+    matches!(self, FTerm::Tuple(_) | FTerm::EmptyTuple)
   }
 
 
@@ -97,11 +230,12 @@ impl FTerm {
 
 
   pub fn is_list(&self) -> bool {
-    match self {
-      FTerm::List(_) => true,
-      FTerm::EmptyList => true,
-      _ => false,
-    }
+    matches!(self, FTerm::List(_) | FTerm::EmptyList)
+  }
+
+
+  pub fn is_improper_list(&self) -> bool {
+    matches!(self, FTerm::ImproperList(_, _))
   }
 
 
@@ -114,12 +248,163 @@ impl FTerm {
   }
 
 
+  pub fn is_map(&self) -> bool {
+    matches!(self, FTerm::Map(_))
+  }
+
+
+  /// Find value by key in a map term.
+  pub fn map_get(&self, key: &FTerm) -> Option<&FTerm> {
+    match self {
+      FTerm::Map(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => panic!("Term must be a map, got {}", self),
+    }
+  }
+
+
   pub fn is_atom_of(&self, s: &str) -> bool {
     match self {
-      FTerm::Atom(s2) => s == s2,
+      FTerm::Atom(s2) => s2 == s,
       _ => false,
     }
   }
+
+
+  /// Atom text, without copying it.
+  pub fn as_atom(&self) -> Option<&str> {
+    match self {
+      FTerm::Atom(s) => Some(s.as_str()),
+      _ => None,
+    }
+  }
+
+
+  /// Value of an integer which fits into i64.
+  pub fn as_i64(&self) -> Option<i64> {
+    match self {
+      FTerm::Int64(i) => Some(*i),
+      _ => None,
+    }
+  }
+
+
+  /// Value of atom `true` or `false`, other atoms are not booleans.
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      FTerm::Atom(s) if s == "true" => Some(true),
+      FTerm::Atom(s) if s == "false" => Some(false),
+      _ => None,
+    }
+  }
+
+
+  /// Elements of a tuple.
+  pub fn as_tuple(&self) -> Option<&[FTerm]> {
+    match self {
+      FTerm::Tuple(v) => Some(v),
+      FTerm::EmptyTuple => Some(&[]),
+      _ => None,
+    }
+  }
+
+
+  /// Elements of a proper list. A `String` is not returned because its
+  /// elements are not stored as terms.
+  pub fn as_list(&self) -> Option<&[FTerm]> {
+    match self {
+      FTerm::List(v) => Some(v),
+      FTerm::EmptyList => Some(&[]),
+      _ => None,
+    }
+  }
+
+
+  /// First element of a tuple if it is an atom, like `k_seq` in
+  /// `{k_seq, Anno, Arg, Body}`.
+  pub fn tuple_tag(&self) -> Option<&str> {
+    match self {
+      FTerm::Tuple(v) => v.first().and_then(|t| t.as_atom()),
+      _ => None,
+    }
+  }
+
+
+  /// Elements of a tuple of `arity` elements whose first element is atom
+  /// `tag`, the tag included.
+  pub fn as_tagged_tuple(&self, tag: &str, arity: usize) -> Option<&[FTerm]> {
+    match self {
+      FTerm::Tuple(v) if v.len() == arity && v.first().is_some_and(|t| t.is_atom_of(tag)) =>
+        Some(v),
+      _ => None,
+    }
+  }
+
+
+  pub fn expect_atom(&self) -> Result<&str, TermError> {
+    self.as_atom().ok_or_else(|| TermError::new("atom", self))
+  }
+
+
+  pub fn expect_i64(&self) -> Result<i64, TermError> {
+    self.as_i64().ok_or_else(|| TermError::new("integer", self))
+  }
+
+
+  pub fn expect_bool(&self) -> Result<bool, TermError> {
+    self.as_bool().ok_or_else(|| TermError::new("true or false", self))
+  }
+
+
+  pub fn expect_tuple(&self) -> Result<&[FTerm], TermError> {
+    self.as_tuple().ok_or_else(|| TermError::new("tuple", self))
+  }
+
+
+  pub fn expect_list(&self) -> Result<&[FTerm], TermError> {
+    self.as_list().ok_or_else(|| TermError::new("list", self))
+  }
+
+
+  /// Same as `as_tagged_tuple`, the error says which shape was expected.
+  pub fn expect_tagged_tuple(&self, tag: &str, arity: usize)
+                             -> Result<&[FTerm], TermError> {
+    self.as_tagged_tuple(tag, arity).ok_or_else(|| {
+      TermError::new(&format!("{{{}, ...}} of {} elements", tag, arity), self)
+    })
+  }
+}
+
+
+/// Returned by the `expect_*` accessors when a term has another shape.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermError {
+  /// What was expected, like "atom" or "{k_seq, ...} of 4 elements"
+  pub expected: String,
+  pub found: FTerm,
+}
+
+
+// Found terms can be whole function bodies, print only the beginning
+const TERM_ERROR_MAX_WIDTH: usize = 200;
+
+
+impl TermError {
+  pub fn new(expected: &str, found: &FTerm) -> TermError {
+    TermError { expected: expected.to_string(), found: found.clone() }
+  }
+}
+
+
+impl fmt::Display for TermError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let found = format!("{}", self.found);
+    if found.chars().count() > TERM_ERROR_MAX_WIDTH {
+      let short: String = found.chars().take(TERM_ERROR_MAX_WIDTH).collect();
+      write!(f, "expected {}, got {}...", self.expected, short)
+    } else {
+      write!(f, "expected {}, got {}", self.expected, found)
+    }
+  }
 }
 
 
@@ -145,75 +430,112 @@ impl fmt::Debug for FTerm {
 }
 
 
-/// Check whether a character belongs to an atom which can be printed without
-/// enclosing it in 'quotes'. First character also cannot begin with a digit
-/// but it is not checked here.
-#[inline]
-fn is_unquoted_atom_character(c: char) -> bool {
-  c.is_ascii_alphanumeric() || c == '_'
-}
-
-
-#[inline]
-fn is_first_unquoted_atom_character(c: char) -> bool {
-  c.is_ascii_lowercase() || c.is_digit(10) || c == '_'
-}
-
-
-/// Check whether a string contains only characters that do not require enclosing
-/// atom with 'single quotes'
-#[inline]
-fn is_unquoted_atom(s: &String) -> bool {
-  if s.is_empty() { return false };
-
-  let (_, first) = s.char_indices().next().unwrap();
-  if !is_first_unquoted_atom_character(first) { return false };
-
-  for (_, c) in s.char_indices() {
-    if !is_unquoted_atom_character(c) { return false }
-  }
-  true
-}
-
-
 impl fmt::Display for FTerm {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      FTerm::Atom(s) => {
-        if is_unquoted_atom(s) { write!(f, "{}", s) }
-        else { write!(f, "'{}'", s) }
-      },
+      FTerm::Atom(s) => write!(f, "{}", pretty::atom_text(s.as_str(), true)),
       FTerm::String(s) => {
-        write!(f, "\"");
-        for (_, c) in s.char_indices() {
-          if c as usize <= 32usize { write!(f, "\\x{:x}", c as usize); }
-          else { write!(f, "{}", c); }
-        }
-        write!(f, "\"")
+        let mut text = String::with_capacity(s.len() + 2);
+        text.push('"');
+        pretty::push_escaped(&mut text, s.chars(), '"', true);
+        text.push('"');
+        write!(f, "{}", text)
       },
       FTerm::Int64(i) => write!(f, "{}", i),
-      FTerm::Float(flt) => write!(f, "{}", flt),
+      FTerm::BigInt(b) => write!(f, "{}", b),
+      FTerm::Float(flt) => write!(f, "{}", pretty::format_float(*flt)),
       FTerm::EmptyList => write!(f, "[]"),
-      FTerm::List(v) => print_list(f, "[", "]", &v),
+      FTerm::List(v) => print_list(f, "[", "]", v),
+      FTerm::ImproperList(v, tail) => {
+        print_list(f, "[", "", v)?;
+        write!(f, " | {}]", tail)
+      },
       FTerm::EmptyTuple => write!(f, "{{}}"),
-      FTerm::Tuple(v) => print_list(f, "{", "}", &v),
-      FTerm::Binary(b) => write!(f, "<<{:?}>>", b),
+      FTerm::Tuple(v) => print_list(f, "{", "}", v),
+      FTerm::Binary(b, bit_size) => print_binary(f, b, *bit_size),
+      FTerm::Map(pairs) => print_map(f, pairs),
+      FTerm::Pid { node, id, serial } => write!(f, "<{}.{}.{}>", node, id, serial),
+      FTerm::Port { node, id } => write!(f, "#Port<{}.{}>", node, id),
+      FTerm::Reference { node, ids } => {
+        write!(f, "#Ref<{}", node)?;
+        for i in ids {
+          write!(f, ".{}", i)?;
+        }
+        write!(f, ">")
+      },
+      FTerm::ExportFun { module, fun, arity } =>
+        write!(f, "#Fun<{}.{}.{}>",
+               pretty::atom_text(module, true), pretty::atom_text(fun, true), arity),
+      FTerm::LocalFun { module, index, uniq, .. } =>
+        write!(f, "#Fun<{}.{}.{}>", pretty::atom_text(module, true), index, uniq),
     }
   }
 }
 
 
+/// Print bytes comma-separated, the trailing bits are printed as `Value:Bits`
+fn print_binary(f: &mut fmt::Formatter, data: &[u8],
+                bit_size: usize) -> fmt::Result {
+  write!(f, "<<")?;
+  for (i, byte) in data[..bit_size / 8].iter().enumerate() {
+    if i > 0 { write!(f, ",")? }
+    write!(f, "{}", byte)?;
+  }
+  let tail_bits = bit_size % 8;
+  if tail_bits > 0 {
+    if bit_size >= 8 { write!(f, ",")? }
+    write!(f, "{}:{}", data[bit_size / 8] >> (8 - tail_bits), tail_bits)?;
+  }
+  write!(f, ">>")
+}
+
+
+fn print_map(f: &mut fmt::Formatter, pairs: &[(FTerm, FTerm)]) -> fmt::Result {
+  write!(f, "#{{")?;
+  let mut first = true;
+  for (k, v) in pairs {
+    if first {
+      first = false;
+    } else {
+      write!(f, ", ")?;
+    }
+    write!(f, "{} => {}", k, v)?;
+  }
+  write!(f, "}}")
+}
+
+
 fn print_list(f: &mut fmt::Formatter, open: &str, close: &str,
-              vec: &Vec<FTerm>) -> fmt::Result {
-  write!(f, "{}", open);
+              vec: &[FTerm]) -> fmt::Result {
+  write!(f, "{}", open)?;
   let mut first = true;
   for ft in vec {
     if first {
       first = false;
     } else {
-      write!(f, ", ");
+      write!(f, ", ")?;
     }
-    write!(f, "{}", ft);
+    write!(f, "{}", ft)?;
   }
   write!(f, "{}", close)
 }
+
+
+/// Big integers as decimal text, which any reader can parse
+#[cfg(feature = "serialize")]
+mod bigint_text {
+  use num_bigint::BigInt;
+  use serde::de::{Deserialize, Deserializer, Error};
+  use serde::ser::Serializer;
+
+
+  pub fn serialize<S: Serializer>(b: &BigInt, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&b.to_string())
+  }
+
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<BigInt, D::Error> {
+    let text = String::deserialize(d)?;
+    text.parse().map_err(|_| D::Error::custom(format!("bad integer {:?}", text)))
+  }
+}
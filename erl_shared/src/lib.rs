@@ -1,11 +1,467 @@
+extern crate num_bigint;
+extern crate num_traits;
+extern crate flate2;
+#[cfg(feature = "serialize")]
+extern crate serde;
+#[cfg(feature = "serialize")]
+#[macro_use]
+extern crate serde_derive;
+
+pub mod atom;
 pub mod types;
 pub mod fterm;
+pub mod term_order;
+pub mod etf;
+pub mod pretty;
 
 
 #[cfg(test)]
 mod tests {
+  use etf;
+  use fterm::{FTerm, Node};
+  use num_bigint::BigInt;
+
   #[test]
   fn it_works() {
     assert_eq!(2 + 2, 4);
   }
+
+
+  fn atom(s: &str) -> FTerm { FTerm::atom(s) }
+
+
+  #[test]
+  fn etf_decode_numbers() {
+    assert_eq!(etf::decode(&[131, 97, 1]), Ok(FTerm::Int64(1)));
+    assert_eq!(etf::decode(&[131, 98, 255, 255, 255, 255]), Ok(FTerm::Int64(-1)));
+    assert_eq!(etf::decode(&[131, 70, 63, 248, 0, 0, 0, 0, 0, 0]), Ok(FTerm::Float(1.5)));
+
+    // 1 bsl 64 and its negation
+    let big = BigInt::from(1u64 << 63) * BigInt::from(2);
+    assert_eq!(etf::decode(&[131, 110, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+               Ok(FTerm::BigInt(big.clone())));
+    assert_eq!(etf::decode(&[131, 111, 0, 0, 0, 9, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+               Ok(FTerm::BigInt(-big)));
+    // Small bignum gets normalized
+    assert_eq!(etf::decode(&[131, 110, 1, 1, 5]), Ok(FTerm::Int64(-5)));
+  }
+
+
+  #[test]
+  fn etf_decode_atoms_and_containers() {
+    assert_eq!(etf::decode(&[131, 100, 0, 1, 97]), Ok(atom("a")));
+    assert_eq!(etf::decode(&[131, 119, 2, 195, 169]), Ok(atom("é")));
+    assert_eq!(etf::decode(&[131, 104, 2, 119, 1, 97, 97, 1]),
+               Ok(FTerm::Tuple(vec![atom("a"), FTerm::Int64(1)])));
+    assert_eq!(etf::decode(&[131, 104, 0]), Ok(FTerm::EmptyTuple));
+    assert_eq!(etf::decode(&[131, 106]), Ok(FTerm::EmptyList));
+    assert_eq!(etf::decode(&[131, 107, 0, 2, 104, 105]),
+               Ok(FTerm::String("hi".to_string())));
+    assert_eq!(etf::decode(&[131, 108, 0, 0, 0, 1, 119, 1, 97, 106]),
+               Ok(FTerm::List(vec![atom("a")])));
+    assert_eq!(etf::decode(&[131, 108, 0, 0, 0, 1, 119, 1, 97, 119, 1, 98]),
+               Ok(FTerm::ImproperList(vec![atom("a")], Box::new(atom("b")))));
+    assert_eq!(etf::decode(&[131, 116, 0, 0, 0, 1, 119, 1, 97, 97, 1]),
+               Ok(FTerm::Map(vec![(atom("a"), FTerm::Int64(1))])));
+    assert_eq!(etf::decode(&[131, 109, 0, 0, 0, 2, 1, 2]),
+               Ok(FTerm::new_binary(vec![1, 2])));
+    assert_eq!(etf::decode(&[131, 77, 0, 0, 0, 1, 3, 32]),
+               Ok(FTerm::Binary(vec![32], 3)));
+  }
+
+
+  #[test]
+  fn etf_decode_opaque() {
+    // <0.85.0> on nonode@nohost, as NEW_PID_EXT
+    let mut data = vec![131, 88, 119, 13];
+    data.extend(b"nonode@nohost".iter());
+    data.extend(&[0, 0, 0, 85, 0, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(etf::decode(&data),
+               Ok(FTerm::Pid { node: Node::Name("nonode@nohost".to_string(), 0),
+                               id: 85,
+                               serial: 0 }));
+
+    // fun lists:map/2
+    let data = [131, 113, 119, 5, 108, 105, 115, 116, 115, 119, 3, 109, 97, 112, 97, 2];
+    assert_eq!(etf::decode(&data),
+               Ok(FTerm::ExportFun { module: "lists".to_string(),
+                                     fun: "map".to_string(),
+                                     arity: 2 }));
+  }
+
+
+  #[test]
+  fn etf_decode_compressed() {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let raw = [108, 0, 0, 0, 2, 97, 1, 97, 2, 106]; // [1, 2] as LIST_EXT
+    let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+    z.write_all(&raw).unwrap();
+    let mut data = vec![131, 80, 0, 0, 0, raw.len() as u8];
+    data.extend(z.finish().unwrap());
+    assert_eq!(etf::decode(&data),
+               Ok(FTerm::List(vec![FTerm::Int64(1), FTerm::Int64(2)])));
+  }
+
+
+  #[test]
+  fn etf_decode_errors() {
+    assert_eq!(etf::decode(&[]), Err(etf::DecodeError::UnexpectedEnd));
+    assert_eq!(etf::decode(&[130, 97, 1]), Err(etf::DecodeError::BadVersion(130)));
+    assert_eq!(etf::decode(&[131, 1]), Err(etf::DecodeError::UnknownTag(1)));
+    assert_eq!(etf::decode(&[131, 104, 2, 97, 1]), Err(etf::DecodeError::UnexpectedEnd));
+    assert_eq!(etf::decode(&[131, 97, 1, 0]), Err(etf::DecodeError::TrailingBytes(1)));
+    // Huge counts for list, map and large tuple, no data behind them
+    for tag in &[108u8, 116, 105] {
+      assert_eq!(etf::decode(&[131, *tag, 255, 255, 255, 255]),
+                 Err(etf::DecodeError::UnexpectedEnd));
+    }
+    // Compressed size is wrong
+    let mut data = etf::encode_compressed(&FTerm::List(vec![atom("a"); 100]), 6).unwrap();
+    assert_eq!(data[1], 80);
+    data[2..6].copy_from_slice(&[255, 255, 255, 255]);
+    assert_eq!(etf::decode(&data), Err(etf::DecodeError::BadCompressed));
+    // Bit binaries with 0 or more than 8 bits in the last byte
+    let bad_bits: [&[u8]; 3] = [&[131, 77, 0, 0, 0, 0, 3],
+                                &[131, 77, 0, 0, 0, 1, 0, 1],
+                                &[131, 77, 0, 0, 0, 1, 9, 1]];
+    for data in &bad_bits {
+      assert_eq!(etf::decode(data), Err(etf::DecodeError::OutOfRange("bits")));
+    }
+    assert_eq!(etf::decode(&[131, 77, 0, 0, 0, 1, 8, 1]), Ok(FTerm::Binary(vec![1], 8)));
+    // Export arity above 255
+    assert_eq!(etf::decode(&[131, 113, 100, 0, 1, 109, 100, 0, 1, 102, 98, 0, 0, 1, 0]),
+               Err(etf::DecodeError::OutOfRange("arity")));
+  }
+
+
+  fn roundtrip(t: FTerm) {
+    let data = etf::encode(&t).unwrap();
+    assert_eq!(etf::decode(&data), Ok(t.clone()), "plain {:?}", data);
+    let data = etf::encode_compressed(&t, 6).unwrap();
+    assert_eq!(etf::decode(&data), Ok(t), "compressed {:?}", data);
+  }
+
+
+  #[test]
+  fn etf_encode_roundtrip() {
+    use fterm::FunEnv;
+
+    let big = BigInt::from(1u64 << 63) * BigInt::from(2);
+    let node = Node::Name("a@host".to_string(), 3);
+    let pid = FTerm::Pid { node: node.clone(), id: 85, serial: 1 };
+    let terms = vec![
+      FTerm::Int64(0), FTerm::Int64(255), FTerm::Int64(-1), FTerm::Int64(1 << 40),
+      FTerm::Int64(i64::MIN),
+      FTerm::BigInt(big.clone()), FTerm::BigInt(-big),
+      FTerm::Float(1.5), FTerm::Float(-0.0),
+      atom("a"), atom("é"), atom(&"é".repeat(255)),
+      FTerm::EmptyTuple, FTerm::EmptyList,
+      FTerm::Tuple(vec![atom("a"), FTerm::Int64(1)]),
+      FTerm::Tuple(vec![FTerm::Int64(1); 300]),
+      FTerm::String("hi".to_string()),
+      FTerm::List(vec![atom("a"), FTerm::String("b".to_string())]),
+      FTerm::ImproperList(vec![atom("a")], Box::new(atom("b"))),
+      FTerm::Map(vec![(atom("a"), FTerm::Int64(1)), (FTerm::Int64(2), FTerm::EmptyList)]),
+      FTerm::new_binary(vec![1, 2, 3]), FTerm::new_binary(vec![]),
+      FTerm::Binary(vec![32], 3),
+      pid.clone(),
+      FTerm::Port { node: node.clone(), id: 5 },
+      FTerm::Port { node: node.clone(), id: 1 << 40 },
+      FTerm::Reference { node: node.clone(), ids: vec![1, 2, 3] },
+      FTerm::ExportFun { module: "lists".to_string(), fun: "map".to_string(), arity: 2 },
+      FTerm::LocalFun {
+        module: "m".to_string(), index: 1, uniq: 12345,
+        env: Some(Box::new(FunEnv { arity: 1, md5: [7; 16], pid,
+                                    free_vars: vec![atom("x"), FTerm::Int64(2)] })),
+      },
+    ];
+    for t in terms {
+      roundtrip(t);
+    }
+  }
+
+
+  #[test]
+  fn etf_encode() {
+    use fterm::FunEnv;
+
+    // Same bytes as term_to_binary produces
+    assert_eq!(etf::encode(&FTerm::Int64(1)), Ok(vec![131, 97, 1]));
+    assert_eq!(etf::encode(&FTerm::Int64(-1)), Ok(vec![131, 98, 255, 255, 255, 255]));
+    assert_eq!(etf::encode(&FTerm::String("hi".to_string())),
+               Ok(vec![131, 107, 0, 2, 104, 105]));
+    // Not latin-1, becomes a list of integers
+    assert_eq!(etf::decode(&etf::encode(&FTerm::String("ā".to_string())).unwrap()),
+               Ok(FTerm::List(vec![FTerm::Int64(257)])));
+    // Local node from the text form gets a placeholder name
+    let pid = FTerm::Pid { node: Node::Index(0), id: 85, serial: 0 };
+    assert_eq!(etf::decode(&etf::encode(&pid).unwrap()),
+               Ok(FTerm::Pid { node: Node::Name("nonode@nohost".to_string(), 0),
+                               id: 85,
+                               serial: 0 }));
+    let fun = FTerm::LocalFun { module: "m".to_string(), index: 0, uniq: 0, env: None };
+    assert_eq!(etf::encode(&fun), Err(etf::EncodeError::MissingFunEnv));
+    assert_eq!(etf::encode(&atom(&"x".repeat(256))), Err(etf::EncodeError::AtomTooLong));
+    // Fun arities are a single byte
+    let fun = FTerm::ExportFun { module: "m".to_string(), fun: "f".to_string(), arity: 256 };
+    assert_eq!(etf::encode(&fun), Err(etf::EncodeError::TooLarge));
+    let env = FunEnv { arity: 256, md5: [0; 16], pid: FTerm::EmptyList, free_vars: vec![] };
+    let fun = FTerm::LocalFun { module: "m".to_string(), index: 0, uniq: 0,
+                                env: Some(Box::new(env)) };
+    assert_eq!(etf::encode(&fun), Err(etf::EncodeError::TooLarge));
+
+    // Compression only kicks in when it helps
+    let short = FTerm::Int64(1);
+    assert_eq!(etf::encode_compressed(&short, 9), etf::encode(&short));
+    let long = FTerm::new_binary(vec![0; 1000]);
+    let packed = etf::encode_compressed(&long, 9).unwrap();
+    assert_eq!(&packed[..2], &[131, 80]);
+    assert!(packed.len() < 100);
+  }
+
+
+  #[test]
+  fn term_order() {
+    use std::cmp::Ordering;
+    use std::collections::HashSet;
+
+    let big = BigInt::from(1u64 << 63) * BigInt::from(2);
+    let node = Node::Name("a@host".to_string(), 0);
+    let sorted = vec![
+      FTerm::BigInt(-big.clone()), FTerm::Int64(-1), FTerm::Float(0.5), FTerm::Int64(1),
+      FTerm::Float(1.5), FTerm::BigInt(big),
+      atom("a"), atom("b"),
+      FTerm::Reference { node: node.clone(), ids: vec![1, 2, 3] },
+      FTerm::ExportFun { module: "m".to_string(), fun: "f".to_string(), arity: 1 },
+      FTerm::LocalFun { module: "m".to_string(), index: 0, uniq: 0, env: None },
+      FTerm::Port { node: node.clone(), id: 1 },
+      FTerm::Pid { node: node.clone(), id: 1, serial: 0 },
+      FTerm::EmptyTuple,
+      FTerm::Tuple(vec![FTerm::Int64(2)]),
+      FTerm::Tuple(vec![FTerm::Int64(1), FTerm::Int64(1)]),
+      FTerm::Map(vec![]),
+      FTerm::Map(vec![(FTerm::Int64(1), atom("a"))]),
+      FTerm::Map(vec![(FTerm::Float(1.0), atom("a"))]),
+      FTerm::EmptyList,
+      FTerm::ImproperList(vec![FTerm::Int64(1)], Box::new(FTerm::Int64(2))),
+      FTerm::List(vec![FTerm::Int64(1), FTerm::Int64(2)]),
+      FTerm::String("b".to_string()),
+      FTerm::new_binary(vec![]),
+      FTerm::Binary(vec![0x80], 1),
+      FTerm::new_binary(vec![0x80]),
+      FTerm::new_binary(vec![0x81]),
+    ];
+    for (i, a) in sorted.iter().enumerate() {
+      for (j, b) in sorted.iter().enumerate() {
+        assert_eq!(a.cmp(b), i.cmp(&j), "{} vs {}", a, b);
+      }
+    }
+    let mut shuffled: Vec<FTerm> = sorted.iter().rev().cloned().collect();
+    shuffled.sort();
+    assert_eq!(shuffled, sorted);
+
+    // == and =:= on numbers
+    assert!(FTerm::Int64(1).equals(&FTerm::Float(1.0)));
+    assert!(!FTerm::Int64(1).exact_equals(&FTerm::Float(1.0)));
+    assert_eq!(FTerm::Int64(1).compare_exact(&FTerm::Float(1.0)), Ordering::Less);
+    assert_eq!(FTerm::Float(1.0).compare_exact(&FTerm::Int64(1)), Ordering::Greater);
+    assert_eq!(FTerm::Float(-0.0).compare(&FTerm::Float(0.0)), Ordering::Equal);
+    assert_eq!(FTerm::Float(-0.0).compare_exact(&FTerm::Float(0.0)), Ordering::Less);
+    assert!(FTerm::Tuple(vec![FTerm::Int64(2)]).equals(&FTerm::Tuple(vec![FTerm::Float(2.0)])));
+    // Precise beyond 2^53
+    let n = 1i64 << 60;
+    assert_eq!(FTerm::Int64(n + 1).compare(&FTerm::Float(n as f64)), Ordering::Greater);
+    // Map keys always compare exactly, values follow the comparison used
+    let m1 = FTerm::Map(vec![(atom("k"), FTerm::Int64(1))]);
+    let m2 = FTerm::Map(vec![(atom("k"), FTerm::Float(1.0))]);
+    assert!(m1.equals(&m2) && m1 != m2);
+    assert!(!sorted[16].equals(&sorted[17]));
+
+    // Equal terms with different representations hash the same
+    let same = vec![
+      (FTerm::String("ab".to_string()),
+       FTerm::List(vec![FTerm::Int64(97), FTerm::Int64(98)])),
+      (FTerm::String(String::new()), FTerm::EmptyList),
+      (FTerm::Tuple(vec![]), FTerm::EmptyTuple),
+      (FTerm::BigInt(BigInt::from(5)), FTerm::Int64(5)),
+      (FTerm::Map(vec![(atom("a"), atom("x")), (atom("b"), atom("y"))]),
+       FTerm::Map(vec![(atom("b"), atom("y")), (atom("a"), atom("x"))])),
+      (FTerm::Binary(vec![0xff], 3), FTerm::Binary(vec![0xe0], 3)),
+    ];
+    for (a, b) in same {
+      assert_eq!(a, b);
+      let set: HashSet<FTerm> = vec![a, b].into_iter().collect();
+      assert_eq!(set.len(), 1);
+    }
+  }
+
+
+  #[test]
+  fn borrowing_accessors() {
+    let seq = FTerm::Tuple(vec![atom("k_seq"), FTerm::EmptyList, atom("a"), atom("b")]);
+    assert_eq!(seq.tuple_tag(), Some("k_seq"));
+    assert_eq!(seq.as_tagged_tuple("k_seq", 4).map(|v| v.len()), Some(4));
+    assert_eq!(seq.as_tagged_tuple("k_seq", 3), None);
+    assert_eq!(seq.as_tagged_tuple("k_alt", 4), None);
+    assert_eq!(seq.expect_tuple().unwrap()[2].as_atom(), Some("a"));
+
+    let err = seq.expect_tagged_tuple("k_alt", 4).unwrap_err();
+    assert_eq!(format!("{}", err),
+               "expected {k_alt, ...} of 4 elements, got {k_seq, [], a, b}");
+    assert_eq!(FTerm::Int64(1).expect_atom().unwrap_err().expected, "atom");
+
+    assert_eq!(atom("true").as_bool(), Some(true));
+    assert_eq!(atom("false").expect_bool(), Ok(false));
+    assert_eq!(atom("maybe").as_bool(), None);
+    assert_eq!(FTerm::Int64(5).as_i64(), Some(5));
+    assert_eq!(FTerm::EmptyList.as_list(), Some(&[][..]));
+    assert_eq!(FTerm::EmptyTuple.expect_tuple(), Ok(&[][..]));
+    assert!(FTerm::String("ab".to_string()).as_list().is_none());
+    assert!(FTerm::Tuple(vec![]).tuple_tag().is_none());
+
+    // Long terms are cut in the message
+    let long = FTerm::List(vec![FTerm::Int64(1000); 1000]);
+    let msg = format!("{}", long.expect_atom().unwrap_err());
+    assert!(msg.ends_with("...") && msg.len() < 300);
+  }
+
+
+  #[test]
+  fn atom_table() {
+    use atom::{Atom, AtomTable, AtomTooLong};
+
+    let a = Atom::new("interned");
+    assert_eq!(a, Atom::from("interned".to_string()));
+    assert_eq!(a.as_str(), "interned");
+    assert!(a == "interned");
+    assert!(Atom::new("b") > Atom::new("a"));
+    assert_eq!(FTerm::atom("x"), FTerm::Atom(Atom::new("x")));
+
+    let mut t = AtomTable::new();
+    let b = t.intern("b");
+    assert_eq!(t.add(Atom::new("a")), 1);
+    assert_eq!(t.add(b), 0);
+    assert_eq!(t.index_of(Atom::new("a")), Some(1));
+    assert_eq!(t.index_of(Atom::new("not_added")), None);
+    assert_eq!(t.get(1), Some(Atom::new("a")));
+    assert_eq!(t.iter().map(|a| a.as_str()).collect::<Vec<_>>(), vec!["b", "a"]);
+    assert_eq!(t.to_bytes(), Ok(vec![0, 0, 0, 2, 1, b'b', 1, b'a']));
+    // 128 two-byte characters do not fit a length byte
+    let long = t.intern(&"é".repeat(128));
+    assert_eq!(t.to_bytes(), Err(AtomTooLong(long)));
+  }
+
+
+  #[test]
+  fn pretty_print() {
+    use pretty::{format_float, pretty, write};
+
+    let string = FTerm::String("a\"b\n".to_string());
+    let codes = FTerm::List(vec![FTerm::Int64(104), FTerm::Int64(105)]);
+    let bin = FTerm::new_binary(b"hi".to_vec());
+    assert_eq!(write(&string), "[97,34,98,10]");
+    assert_eq!(write(&codes), "[104,105]");
+    assert_eq!(write(&bin), "<<104,105>>");
+    assert_eq!(pretty(&string, 1, 80), "\"a\\\"b\\n\"");
+    assert_eq!(pretty(&codes, 1, 80), "\"hi\"");
+    assert_eq!(pretty(&bin, 1, 80), "<<\"hi\">>");
+    assert_eq!(pretty(&FTerm::new_binary(vec![0, 1]), 1, 80), "<<0,1>>");
+    assert_eq!(pretty(&FTerm::String("\u{100}".to_string()), 1, 80), "[256]");
+
+    assert_eq!(write(&atom("ok")), "ok");
+    assert_eq!(write(&atom("Var")), "'Var'");
+    assert_eq!(write(&atom("1a")), "'1a'");
+    assert_eq!(write(&atom("a@b_C1")), "a@b_C1");
+    assert_eq!(write(&atom("receive")), "'receive'");
+    assert_eq!(write(&atom("it's\t")), "'it\\'s\\t'");
+    assert_eq!(write(&atom("\u{1}\u{7f}\u{3b1}")), "'\\001\\d\\x{3B1}'");
+    assert_eq!(write(&atom("")), "''");
+
+    let map = FTerm::new_map(vec![(atom("b"), FTerm::Int64(2)),
+                                  (FTerm::Int64(1), FTerm::EmptyList)]);
+    assert_eq!(write(&map), "#{1 => [],b => 2}");
+    assert_eq!(format!("{}", map), "#{1 => [], b => 2}");
+    assert_eq!(write(&FTerm::new_improper_list(vec![FTerm::Int64(1)], atom("t"))),
+               "[1|t]");
+    let fun = FTerm::ExportFun { module: "m".to_string(), fun: "f".to_string(), arity: 1 };
+    assert_eq!(write(&fun), "fun m:f/1");
+
+    assert_eq!(format_float(1.5), "1.5");
+    assert_eq!(format_float(100.0), "100.0");
+    assert_eq!(format_float(1000.0), "1.0e3");
+    assert_eq!(format!("{}", FTerm::Float(1000.0)), "1.0e3");
+    assert_eq!(format_float(0.0001), "0.0001");
+    assert_eq!(format_float(0.00001), "1.0e-5");
+    assert_eq!(format_float(-2.5e100), "-2.5e100");
+    assert_eq!(format_float(-0.0), "-0.0");
+
+    // Broken lists put one element per line
+    let pairs = FTerm::List(vec![
+      FTerm::Tuple(vec![atom("a"), FTerm::Int64(1)]),
+      FTerm::Tuple(vec![atom("b"), FTerm::Int64(2)]),
+    ]);
+    assert_eq!(pretty(&pairs, 1, 80), "[{a,1},{b,2}]");
+    assert_eq!(pretty(&pairs, 1, 10), "[{a,1},\n {b,2}]");
+    assert_eq!(pretty(&pairs, 3, 12), "[{a,1},\n   {b,2}]");
+
+    // Tag tuples align elements after the tag, short atomic ones share a line
+    let tagged = FTerm::Tuple(vec![
+      atom("tag"), atom("aaaa"),
+      FTerm::List(vec![FTerm::Int64(1), FTerm::Int64(2), FTerm::Int64(3)]),
+      atom("bbbb"),
+    ]);
+    assert_eq!(pretty(&tagged, 1, 16), "{tag,aaaa,\n     [1,2,3],\n     bbbb}");
+
+    // Map pairs share a line while the width allows
+    let map = FTerm::new_map(vec![
+      (atom("a"), FTerm::Int64(1)), (atom("b"), FTerm::Int64(2)), (atom("c"), FTerm::Int64(3)),
+    ]);
+    assert_eq!(pretty(&map, 1, 20), "#{a => 1,b => 2,\n  c => 3}");
+    assert_eq!(pretty(&map, 1, 16), "#{a => 1,\n  b => 2,\n  c => 3}");
+  }
+
+
+  #[test]
+  fn display_escapes() {
+    assert_eq!(format!("{}", atom("Var")), "'Var'");
+    assert_eq!(format!("{}", atom("a'b")), "'a\\'b'");
+    assert_eq!(format!("{}", atom("\u{3b1}")), "'\u{3b1}'");
+    assert_eq!(format!("{}", atom("node@host")), "node@host");
+    assert_eq!(format!("{}", FTerm::String("\"x\ty\\\u{3b1}".to_string())),
+               "\"\\\"x\\ty\\\\\u{3b1}\"");
+  }
+
+
+  #[cfg(feature = "serialize")]
+  #[test]
+  fn serialize() {
+    extern crate serde_json;
+    use atom::AtomTable;
+
+    let big: BigInt = "123456789012345678901234567890".parse().unwrap();
+    let t = FTerm::Tuple(vec![
+      atom("ok"),
+      FTerm::from_bigint(big),
+      FTerm::new_binary(vec![1, 2]),
+      FTerm::Pid { node: Node::Index(0), id: 85, serial: 0 },
+    ]);
+    let json = serde_json::to_string(&t).unwrap();
+    assert_eq!(json, concat!(
+      r#"{"type":"tuple","value":[{"type":"atom","value":"ok"},"#,
+      r#"{"type":"big_int","value":"123456789012345678901234567890"},"#,
+      r#"{"type":"binary","value":[[1,2],16]},"#,
+      r#"{"type":"pid","value":{"node":{"type":"index","value":0},"id":85,"serial":0}}]}"#));
+    assert_eq!(serde_json::from_str::<FTerm>(&json).unwrap(), t);
+
+    let mut atoms = AtomTable::new();
+    atoms.intern("b");
+    atoms.intern("a");
+    let json = serde_json::to_string(&atoms).unwrap();
+    assert_eq!(json, r#"["b","a"]"#);
+    let back: AtomTable = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.index_of(::atom::Atom::new("a")), Some(1));
+  }
 }
@@ -0,0 +1,704 @@
+//! Prints terms as `io_lib:format` does for `~w` and `~p`, following the
+//! layout rules of `io_lib_pretty`. Strings and atoms are escaped for the
+//! latin1 character set, which is what `~p` without the `t` modifier uses.
+
+use fterm::FTerm;
+
+
+/// Line length used by `~p` when the format does not give one.
+pub const DEFAULT_LINE_LENGTH: usize = 80;
+
+
+/// Print the term on one line as `~w` does: no spaces, lists of integers are
+/// not shown as strings.
+pub fn write(term: &FTerm) -> String {
+  let mut out = String::new();
+  flatten(&build(term, false), &mut out);
+  out
+}
+
+
+/// Print the term as `~Np` does (`N` is `line_length`), when the output
+/// starts at `column`, counting from 1. Terms which do not fit are broken
+/// over lines, every next line is indented to start after `column`.
+pub fn pretty(term: &FTerm, column: usize, line_length: usize) -> String {
+  let item = build(term, true);
+  let ll = line_length as i64;
+  let col = column.max(1) as i64;
+  if item.len < ll - col {
+    let mut out = String::new();
+    flatten(&item, &mut out);
+    return out
+  }
+  let printer = |tind| Printer { out: String::new(), ll, m: item.len, tind };
+  // Prefer aligning tag tuple elements after the tag, fall back to smaller
+  // indents when that would run past the line end
+  let tind = [-1, 4].iter().cloned()
+    .find(|i| printer(*i).cind(&item, col, 0, 0).is_ok())
+    .unwrap_or(1);
+  let mut p = printer(tind);
+  let ind = " ".repeat(col as usize - 1);
+  p.pp(&item, col, &ind, 0, 0);
+  p.out
+}
+
+
+/// Check whether the atom text can be printed without quotes.
+pub fn is_unquoted_atom(s: &str) -> bool {
+  if RESERVED_WORDS.contains(&s) {
+    return false
+  }
+  let mut chars = s.chars();
+  match chars.next() {
+    Some(c) if is_lower(c) => chars.all(is_name_char),
+    _ => false,
+  }
+}
+
+
+/// Atom text, in quotes if needed. With `unicode` false characters above 255
+/// are written as `\x{..}` escapes.
+pub fn atom_text(s: &str, unicode: bool) -> String {
+  if is_unquoted_atom(s) {
+    return s.to_string()
+  }
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('\'');
+  push_escaped(&mut out, s.chars(), '\'', unicode);
+  out.push('\'');
+  out
+}
+
+
+/// Append characters escaped as inside a `quote`-delimited Erlang string or
+/// atom. Without `unicode` only latin1 characters are printed as they are.
+pub fn push_escaped<I>(out: &mut String, chars: I, quote: char, unicode: bool)
+  where I: IntoIterator<Item=char> {
+  for c in chars {
+    let code = c as u32;
+    match c {
+      _ if c == quote => { out.push('\\'); out.push(c) },
+      '\\' => out.push_str("\\\\"),
+      ' '..='~' => out.push(c),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      '\x0b' => out.push_str("\\v"),
+      '\x08' => out.push_str("\\b"),
+      '\x0c' => out.push_str("\\f"),
+      '\x1b' => out.push_str("\\e"),
+      '\x7f' => out.push_str("\\d"),
+      _ if code < 0xa0 => out.push_str(&format!("\\{:03o}", code)),
+      _ if code < 0x100 || unicode => out.push(c),
+      _ => out.push_str(&format!("\\x{{{:X}}}", code)),
+    }
+  }
+}
+
+
+/// Format a float as Erlang does: the shortest digits which read back as the
+/// same value, in exponent form only when that is shorter (`1.0e3`, `0.001`).
+pub fn format_float(f: f64) -> String {
+  if f == 0.0 {
+    return if f.is_sign_negative() { "-0.0" } else { "0.0" }.to_string()
+  }
+  let sci = format!("{:e}", f.abs());
+  let epos = sci.find('e').unwrap();
+  let digits: String = sci[..epos].chars().filter(|c| *c != '.').collect();
+  let exp: i64 = sci[epos + 1..].parse().unwrap();
+  let sign = if f < 0.0 { "-" } else { "" };
+  format!("{}{}", sign, insert_decimal(exp + 1, &digits))
+}
+
+
+/// Place the decimal point `place` digits into `digits`, as
+/// `io_lib_format:insert_decimal` does.
+fn insert_decimal(place: i64, digits: &str) -> String {
+  let len = digits.len() as i64;
+  let exp = place - 1;
+  let exp_text = exp.to_string();
+  let exp_cost = exp_text.len() as i64 + 1 + if len == 1 { 2 } else { 1 };
+  if place <= 0 {
+    if -place + 2 <= exp_cost {
+      format!("0.{}{}", "0".repeat(-place as usize), digits)
+    } else {
+      insert_exp(&exp_text, digits)
+    }
+  } else if place < len {
+    format!("{}.{}", &digits[..place as usize], &digits[place as usize..])
+  } else if place - len + 2 <= exp_cost {
+    format!("{}{}.0", digits, "0".repeat((place - len) as usize))
+  } else {
+    insert_exp(&exp_text, digits)
+  }
+}
+
+
+fn insert_exp(exp: &str, digits: &str) -> String {
+  let frac = if digits.len() == 1 { "0" } else { &digits[1..] };
+  format!("{}.{}e{}", &digits[..1], frac, exp)
+}
+
+
+const RESERVED_WORDS: [&str; 27] = [
+  "after", "and", "andalso", "band", "begin", "bnot", "bor", "bsl", "bsr",
+  "bxor", "case", "catch", "cond", "div", "end", "fun", "if", "let", "not",
+  "of", "or", "orelse", "receive", "rem", "try", "when", "xor",
+];
+
+
+fn is_lower(c: char) -> bool {
+  c.is_ascii_lowercase() || (('ß'..='ÿ').contains(&c) && c != '÷')
+}
+
+
+fn is_name_char(c: char) -> bool {
+  is_lower(c)
+    || c.is_ascii_uppercase()
+    || (('À'..='Þ').contains(&c) && c != '×')
+    || c.is_ascii_digit()
+    || c == '_'
+    || c == '@'
+}
+
+
+/// Characters `~p` accepts in a string
+fn is_printable(code: i64) -> bool {
+  matches!(code, 32..=126 | 160..=255 | 8..=13 | 27)
+}
+
+
+/// Term with the length of its one-line form, the shape tells how it may be
+/// broken over lines (`print_length` in `io_lib_pretty`).
+struct Item {
+  shape: Shape,
+  len: i64,
+}
+
+
+enum Shape {
+  /// Printed as is, never broken
+  Flat(String),
+  /// Binary `<<1,2,3>>` which may be wrapped between bytes
+  Bin(String),
+  /// Elements and the improper tail
+  List(Vec<Item>, Option<Box<Item>>),
+  /// Elements, whether the first one is an atom tag
+  Tuple(Vec<Item>, bool),
+  Map(Vec<Pair>),
+}
+
+
+struct Pair {
+  key: Item,
+  value: Item,
+  len: i64,
+}
+
+
+fn flat(s: String) -> Item {
+  Item { len: s.chars().count() as i64, shape: Shape::Flat(s) }
+}
+
+
+/// Length of a bracketed sequence: brackets plus elements and separators
+fn seq_len(brackets: i64, lens: &[i64]) -> i64 {
+  brackets + lens.iter().sum::<i64>() + (lens.len() as i64 - 1).max(0)
+}
+
+
+fn build_list(elements: Vec<Item>, tail: Option<Item>) -> Item {
+  let mut lens: Vec<i64> = elements.iter().map(|e| e.len).collect();
+  if let Some(ref t) = tail {
+    lens.push(t.len);
+  }
+  Item { len: seq_len(2, &lens), shape: Shape::List(elements, tail.map(Box::new)) }
+}
+
+
+/// With `strings` lists and binaries of printable characters print as
+/// strings, as `~p` does
+fn build(term: &FTerm, strings: bool) -> Item {
+  match term {
+    FTerm::Atom(a) => flat(atom_text(a.as_str(), false)),
+    FTerm::Int64(i) => flat(i.to_string()),
+    FTerm::BigInt(b) => flat(b.to_string()),
+    FTerm::Float(f) => flat(format_float(*f)),
+    FTerm::EmptyList => flat("[]".to_string()),
+    FTerm::EmptyTuple => flat("{}".to_string()),
+
+    FTerm::String(s) => {
+      let codes: Vec<i64> = s.chars().map(|c| c as i64).collect();
+      build_codes(&codes, strings)
+    },
+    FTerm::List(v) => {
+      if strings {
+        let codes: Option<Vec<i64>> = v.iter().map(|e| e.as_i64()).collect();
+        if let Some(codes) = codes {
+          return build_codes(&codes, strings)
+        }
+      }
+      build_list(v.iter().map(|e| build(e, strings)).collect(), None)
+    },
+    FTerm::ImproperList(v, tail) =>
+      build_list(v.iter().map(|e| build(e, strings)).collect(),
+                 Some(build(tail, strings))),
+
+    FTerm::Tuple(v) => {
+      let elements: Vec<Item> = v.iter().map(|e| build(e, strings)).collect();
+      let lens: Vec<i64> = elements.iter().map(|e| e.len).collect();
+      let tagged = v.len() > 1 && v[0].is_atom();
+      Item { len: seq_len(2, &lens), shape: Shape::Tuple(elements, tagged) }
+    },
+
+    FTerm::Map(pairs) if pairs.is_empty() => flat("#{}".to_string()),
+    FTerm::Map(pairs) => {
+      let mut sorted: Vec<&(FTerm, FTerm)> = pairs.iter().collect();
+      sorted.sort_by(|a, b| a.0.compare_exact(&b.0));
+      let pairs: Vec<Pair> = sorted.iter().map(|(k, v)| {
+        let key = build(k, strings);
+        let value = build(v, strings);
+        Pair { len: key.len + value.len + 4, key, value }
+      }).collect();
+      let lens: Vec<i64> = pairs.iter().map(|p| p.len).collect();
+      Item { len: seq_len(3, &lens), shape: Shape::Map(pairs) }
+    },
+
+    FTerm::Binary(data, bit_size) => {
+      if *bit_size == 0 {
+        return flat("<<>>".to_string())
+      }
+      if strings && bit_size % 8 == 0
+          && data.iter().all(|b| is_printable(*b as i64)) {
+        let mut s = "<<\"".to_string();
+        push_escaped(&mut s, data.iter().map(|b| *b as char), '"', false);
+        s.push_str("\">>");
+        return flat(s)
+      }
+      let s = format!("{}", term);
+      Item { len: s.len() as i64, shape: Shape::Bin(s) }
+    },
+
+    FTerm::ExportFun { module, fun, arity } =>
+      flat(format!("fun {}:{}/{}", atom_text(module, false),
+                   atom_text(fun, false), arity)),
+    // Pids, ports, references and local funs have the same text form
+    _ => flat(format!("{}", term)),
+  }
+}
+
+
+fn build_codes(codes: &[i64], strings: bool) -> Item {
+  if codes.is_empty() {
+    return flat("[]".to_string())
+  }
+  if strings && codes.iter().all(|c| is_printable(*c)) {
+    let mut s = "\"".to_string();
+    push_escaped(&mut s, codes.iter().map(|c| *c as u8 as char), '"', false);
+    s.push('"');
+    return flat(s)
+  }
+  build_list(codes.iter().map(|c| flat(c.to_string())).collect(), None)
+}
+
+
+/// One-line form of an item
+fn flatten(item: &Item, out: &mut String) {
+  match &item.shape {
+    Shape::Flat(s) | Shape::Bin(s) => out.push_str(s),
+    Shape::List(elements, tail) => {
+      out.push('[');
+      flatten_seq(elements, out);
+      if let Some(t) = tail {
+        out.push('|');
+        flatten(t, out);
+      }
+      out.push(']');
+    },
+    Shape::Tuple(elements, _) => {
+      out.push('{');
+      flatten_seq(elements, out);
+      out.push('}');
+    },
+    Shape::Map(pairs) => {
+      out.push_str("#{");
+      for (i, p) in pairs.iter().enumerate() {
+        if i > 0 { out.push(',') }
+        flatten_pair(p, out);
+      }
+      out.push('}');
+    },
+  }
+}
+
+
+fn flatten_seq(elements: &[Item], out: &mut String) {
+  for (i, e) in elements.iter().enumerate() {
+    if i > 0 { out.push(',') }
+    flatten(e, out);
+  }
+}
+
+
+fn flatten_pair(p: &Pair, out: &mut String) {
+  flatten(&p.key, out);
+  out.push_str(" => ");
+  flatten(&p.value, out);
+}
+
+
+fn is_atomic(item: &Item) -> bool {
+  matches!(item.shape, Shape::Flat(_))
+}
+
+
+fn is_atomic_pair(p: &Pair) -> bool {
+  is_atomic(&p.key) && is_atomic(&p.value)
+}
+
+
+/// Depth of closing brackets after an element, `rest` tells whether more
+/// elements follow it
+fn last_depth(rest: bool, ld: i64) -> i64 {
+  if rest { 0 } else { ld + 1 }
+}
+
+
+fn indent(n: i64, ind: &str) -> String {
+  let mut s = ind.to_string();
+  s.push_str(&" ".repeat(n.max(0) as usize));
+  s
+}
+
+
+/// Returned by the indentation checks when the term cannot be laid out
+/// within the line length
+struct NoGood;
+
+
+type Fits = Result<(), NoGood>;
+
+
+/// Layout state. Columns count from 1; `ld` is the number of closing
+/// brackets which follow on the same line and `w` the width used so far by
+/// the enclosing element.
+struct Printer {
+  out: String,
+  ll: i64,
+  /// Length of the whole term, no line can be longer than that
+  m: i64,
+  /// Indent for tag tuple elements, -1 to align them after the tag
+  tind: i64,
+}
+
+
+impl Printer {
+  fn fits(&self, len: i64, col: i64, ld: i64, w: i64) -> bool {
+    len < self.ll - col - ld && len + w + ld <= self.m
+  }
+
+
+  /// Whether an element can follow on the same line after a comma
+  fn fits_after(&self, item_len: i64, col: i64, ld1: i64, w: i64) -> bool {
+    let elen = 1 + item_len;
+    if ld1 == 0 {
+      elen + 1 < self.ll - col && w + elen < self.m
+    } else {
+      elen < self.ll - col - ld1 && w + elen + ld1 <= self.m
+    }
+  }
+
+
+  /// Whether the improper tail of a list can follow `|` on the same line
+  fn fits_tail(&self, t: &Item, col: i64, ld: i64, w: i64) -> bool {
+    is_atomic(t) && t.len + 1 < self.ll - col - (ld + 1) && t.len + 1 + w + ld < self.m
+  }
+
+
+  fn pp(&mut self, item: &Item, col: i64, ind: &str, ld: i64, w: i64) {
+    if self.fits(item.len, col, ld, w) {
+      flatten(item, &mut self.out);
+      return
+    }
+    match &item.shape {
+      Shape::Flat(s) => self.out.push_str(s),
+      Shape::Bin(s) => self.pp_binary(s, col, ind, ld, w),
+      Shape::List(elements, tail) => {
+        self.out.push('[');
+        self.pp_list(elements, tail.as_ref().map(|t| &**t), col + 1,
+                     &indent(1, ind), ld, w + 1);
+        self.out.push(']');
+      },
+      Shape::Tuple(elements, true) => {
+        self.out.push('{');
+        self.pp_tag_tuple(elements, col, ind, ld, w + 1);
+        self.out.push('}');
+      },
+      Shape::Tuple(elements, false) => {
+        self.out.push('{');
+        self.pp_list(elements, None, col + 1, &indent(1, ind), ld, w + 1);
+        self.out.push('}');
+      },
+      Shape::Map(pairs) => {
+        self.out.push_str("#{");
+        self.pp_map(pairs, col + 2, &indent(2, ind), ld, w + 2);
+        self.out.push('}');
+      },
+    }
+  }
+
+
+  fn pp_tag_tuple(&mut self, elements: &[Item], col: i64, ind: &str, ld: i64,
+                  w: i64) {
+    let tlen = elements[0].len;
+    let tag_ind = tlen + 2;
+    let tcol = col + tag_ind;
+    flatten(&elements[0], &mut self.out);
+    if self.tind > 0 && tag_ind > self.tind {
+      let ind = indent(self.tind, ind);
+      self.pp_tail(&elements[1..], col + self.tind, tcol, &ind, ld, w + tlen);
+    } else {
+      let ind = indent(tag_ind, ind);
+      self.out.push(',');
+      self.pp_list(&elements[1..], None, tcol, &ind, ld, w + tlen + 1);
+    }
+  }
+
+
+  fn pp_list(&mut self, elements: &[Item], tail: Option<&Item>, col0: i64,
+             ind: &str, ld: i64, w: i64) {
+    if elements.is_empty() {
+      return
+    }
+    let we = self.pp_element(&elements[0], col0, ind,
+                             last_depth(elements.len() > 1, ld), w);
+    let (col, w) = self.pp_tail(&elements[1..], col0, col0 + we, ind, ld, w + we);
+    if let Some(t) = tail {
+      self.out.push('|');
+      if self.fits_tail(t, col, ld, w) {
+        flatten(t, &mut self.out);
+      } else {
+        self.out.push('\n');
+        self.out.push_str(ind);
+        self.pp(t, col0, ind, ld + 1, 0);
+      }
+    }
+  }
+
+
+  /// Print the elements after the first one, return the column and width
+  /// reached
+  fn pp_tail(&mut self, elements: &[Item], col0: i64, mut col: i64, ind: &str,
+             ld: i64, mut w: i64) -> (i64, i64) {
+    for (i, e) in elements.iter().enumerate() {
+      let ld1 = last_depth(i + 1 < elements.len(), ld);
+      if is_atomic(e) && self.fits_after(e.len, col, ld1, w) {
+        self.out.push(',');
+        flatten(e, &mut self.out);
+        col += 1 + e.len;
+        w += 1 + e.len;
+      } else {
+        self.out.push_str(",\n");
+        self.out.push_str(ind);
+        let we = self.pp_element(e, col0, ind, ld1, 0);
+        col = col0 + we;
+        w = we;
+      }
+    }
+    (col, w)
+  }
+
+
+  /// Print an element, return its width; the line length if the next
+  /// element has to start on a new line
+  fn pp_element(&mut self, e: &Item, col: i64, ind: &str, ld: i64, w: i64) -> i64 {
+    if is_atomic(e) && self.fits(e.len, col, ld, w) {
+      flatten(e, &mut self.out);
+      return e.len
+    }
+    self.pp(e, col, ind, ld, w);
+    self.ll
+  }
+
+
+  fn pp_map(&mut self, pairs: &[Pair], col0: i64, ind: &str, ld: i64, w: i64) {
+    let pw = self.pp_pair(&pairs[0], col0, ind, last_depth(pairs.len() > 1, ld), w);
+    let mut col = col0 + pw;
+    let mut w = w + pw;
+    for (i, p) in pairs.iter().enumerate().skip(1) {
+      let ld1 = last_depth(i + 1 < pairs.len(), ld);
+      if is_atomic_pair(p) && self.fits_after(p.len, col, ld1, w) {
+        self.out.push(',');
+        flatten_pair(p, &mut self.out);
+        col += 1 + p.len;
+        w += 1 + p.len;
+      } else {
+        self.out.push_str(",\n");
+        self.out.push_str(ind);
+        w = self.pp_pair(p, col0, ind, ld1, 0);
+        col = col0 + w;
+      }
+    }
+  }
+
+
+  fn pp_pair(&mut self, p: &Pair, col: i64, ind: &str, ld: i64, w: i64) -> i64 {
+    if self.fits(p.len, col, ld, w) {
+      flatten_pair(p, &mut self.out);
+      return if is_atomic_pair(p) { p.len } else { self.ll }
+    }
+    let vind = map_value_indent(self.tind);
+    let value_ind = indent(vind, ind);
+    self.pp(&p.key, col, ind, ld, w);
+    self.out.push_str(" =>\n");
+    self.out.push_str(&value_ind);
+    self.pp(&p.value, col + vind, &value_ind, ld, 0);
+    self.ll
+  }
+
+
+  /// Wrap the bytes of `<<1,2,3>>` so that lines stay within the length
+  fn pp_binary(&mut self, s: &str, col: i64, ind: &str, ld: i64, w: i64) {
+    let n0 = (self.ll - col).min(self.m - 4 - w) - ld;
+    let n0 = n0.max(8);
+    let mut n = n0;
+    let body = &s[2..s.len() - 2];
+    let parts: Vec<&str> = body.split(',').collect();
+    self.out.push_str("<<");
+    for (i, part) in parts.iter().enumerate() {
+      let len = part.len() as i64;
+      if i + 1 < parts.len() {
+        if n - len - 1 < 0 {
+          self.out.push('\n');
+          self.out.push_str(ind);
+          n = n0 - len - 1;
+        } else {
+          n -= len + 1;
+        }
+        self.out.push_str(part);
+        self.out.push(',');
+      } else {
+        if len > n {
+          self.out.push('\n');
+          self.out.push_str(ind);
+        }
+        self.out.push_str(part);
+      }
+    }
+    self.out.push_str(">>");
+  }
+
+
+  /// Check whether the term can be printed with the tag tuple indent
+  /// (`cind` in `io_lib_pretty`)
+  fn cind(&self, item: &Item, col: i64, ld: i64, w: i64) -> Fits {
+    if self.fits(item.len, col, ld, w) {
+      return Ok(())
+    }
+    match &item.shape {
+      Shape::List(elements, tail) =>
+        self.cind_list(elements, tail.as_ref().map(|t| &**t), col + 1, ld, w + 1),
+      Shape::Tuple(elements, true) => self.cind_tag_tuple(elements, col, ld, w + 1),
+      Shape::Tuple(elements, false) => self.cind_list(elements, None, col + 1, ld, w + 1),
+      Shape::Map(pairs) => self.cind_map(pairs, col + 2, ld, w + 2),
+      Shape::Bin(_) => Ok(()),
+      Shape::Flat(_) if item.len < self.ll - col - ld => Ok(()),
+      Shape::Flat(_) => Err(NoGood),
+    }
+  }
+
+
+  fn cind_tag_tuple(&self, elements: &[Item], col: i64, ld: i64, w: i64) -> Fits {
+    let tlen = elements[0].len;
+    let tag_ind = tlen + 2;
+    let tcol = col + tag_ind;
+    if self.tind > 0 && tag_ind > self.tind {
+      let col1 = col + self.tind;
+      if self.m + col1 <= self.ll || col1 <= self.ll / 2 {
+        self.cind_tail(&elements[1..], col1, tcol, ld, w + tlen).map(|_| ())
+      } else {
+        Err(NoGood)
+      }
+    } else if self.m + tcol < self.ll || tcol < self.ll / 2 {
+      self.cind_list(&elements[1..], None, tcol, ld, w + tlen + 1)
+    } else {
+      Err(NoGood)
+    }
+  }
+
+
+  fn cind_list(&self, elements: &[Item], tail: Option<&Item>, col0: i64, ld: i64,
+               w: i64) -> Fits {
+    if elements.is_empty() {
+      return Ok(())
+    }
+    let we = self.cind_element(&elements[0], col0, last_depth(elements.len() > 1, ld), w)?;
+    let (col, w) = self.cind_tail(&elements[1..], col0, col0 + we, ld, w + we)?;
+    match tail {
+      Some(t) if self.fits_tail(t, col, ld, w) => Ok(()),
+      Some(t) => self.cind(t, col, ld + 1, 0),
+      None => Ok(()),
+    }
+  }
+
+
+  /// Check the elements after the first one, return the column and width
+  /// reached
+  fn cind_tail(&self, elements: &[Item], col0: i64, mut col: i64, ld: i64,
+               mut w: i64) -> Result<(i64, i64), NoGood> {
+    for (i, e) in elements.iter().enumerate() {
+      let ld1 = last_depth(i + 1 < elements.len(), ld);
+      if is_atomic(e) && self.fits_after(e.len, col, ld1, w) {
+        col += 1 + e.len;
+        w += 1 + e.len;
+      } else {
+        let we = self.cind_element(e, col0, ld1, 0)?;
+        col = col0 + we;
+        w = we;
+      }
+    }
+    Ok((col, w))
+  }
+
+
+  fn cind_element(&self, e: &Item, col: i64, ld: i64, w: i64) -> Result<i64, NoGood> {
+    if is_atomic(e) && self.fits(e.len, col, ld, w) {
+      return Ok(e.len)
+    }
+    self.cind(e, col, ld, w)?;
+    Ok(self.ll)
+  }
+
+
+  fn cind_map(&self, pairs: &[Pair], col0: i64, ld: i64, w: i64) -> Fits {
+    let pw = self.cind_pair(&pairs[0], col0, last_depth(pairs.len() > 1, ld), w)?;
+    let mut col = col0 + pw;
+    let mut w = w + pw;
+    for (i, p) in pairs.iter().enumerate().skip(1) {
+      let ld1 = last_depth(i + 1 < pairs.len(), ld);
+      if is_atomic_pair(p) && self.fits_after(p.len, col, ld1, w) {
+        col += 1 + p.len;
+        w += 1 + p.len;
+      } else {
+        w = self.cind_pair(p, col0, ld1, 0)?;
+        col = col0 + w;
+      }
+    }
+    Ok(())
+  }
+
+
+  fn cind_pair(&self, p: &Pair, col: i64, ld: i64, w: i64) -> Result<i64, NoGood> {
+    if self.fits(p.len, col, ld, w) {
+      return Ok(if is_atomic_pair(p) { p.len } else { self.ll })
+    }
+    self.cind(&p.key, col, ld, w)?;
+    self.cind(&p.value, col + map_value_indent(self.tind), ld, 0)?;
+    Ok(self.ll)
+  }
+}
+
+
+fn map_value_indent(tind: i64) -> i64 {
+  if tind > 0 { tind } else { 4 }
+}
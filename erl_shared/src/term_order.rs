@@ -0,0 +1,377 @@
+//! Erlang standard term order:
+//! number < atom < reference < fun < port < pid < tuple < map < nil < list
+//! < bitstring.
+//!
+//! Two comparisons exist as in Erlang: `compare` (`<`, `==`) where `1 == 1.0`,
+//! and `compare_exact` (`=:=`) where integers and floats never are equal.
+//! The exact one is used for `PartialEq`, `Eq`, `Ord` and `Hash`.
+
+use fterm::{FTerm, FunEnv};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+use std::borrow::{Borrow, Cow};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+
+const NUMBER_RANK: u8 = 0;
+const FUN_RANK: u8 = 3;
+const NIL_RANK: u8 = 8;
+const LIST_RANK: u8 = 9;
+
+
+/// Position of the term's type in the standard order
+fn type_rank(t: &FTerm) -> u8 {
+  match t {
+    FTerm::Int64(_) | FTerm::BigInt(_) | FTerm::Float(_) => NUMBER_RANK,
+    FTerm::Atom(_) => 1,
+    FTerm::Reference { .. } => 2,
+    FTerm::ExportFun { .. } | FTerm::LocalFun { .. } => FUN_RANK,
+    FTerm::Port { .. } => 4,
+    FTerm::Pid { .. } => 5,
+    FTerm::Tuple(_) | FTerm::EmptyTuple => 6,
+    FTerm::Map(_) => 7,
+    FTerm::EmptyList => NIL_RANK,
+    FTerm::String(s) if s.is_empty() => NIL_RANK,
+    FTerm::List(v) if v.is_empty() => NIL_RANK,
+    FTerm::String(_) | FTerm::List(_) | FTerm::ImproperList(_, _) => LIST_RANK,
+    FTerm::Binary(_, _) => 10,
+  }
+}
+
+
+impl FTerm {
+  /// Compare like Erlang `<` and `==` do, an integer equals a float with the
+  /// same value.
+  pub fn compare(&self, other: &FTerm) -> Ordering {
+    cmp_terms(self, other, false)
+  }
+
+
+  /// Total order used by `Ord`. Same as `compare` but an integer sorts before
+  /// a float with the same value, and -0.0 before 0.0, so `Equal` means the
+  /// terms are `=:=`. This is also the order of map keys.
+  pub fn compare_exact(&self, other: &FTerm) -> Ordering {
+    cmp_terms(self, other, true)
+  }
+
+
+  /// Erlang `==`
+  pub fn equals(&self, other: &FTerm) -> bool {
+    self.compare(other) == Ordering::Equal
+  }
+
+
+  /// Erlang `=:=`, same as the `==` operator on FTerm
+  pub fn exact_equals(&self, other: &FTerm) -> bool {
+    self.compare_exact(other) == Ordering::Equal
+  }
+}
+
+
+fn cmp_terms(a: &FTerm, b: &FTerm, exact: bool) -> Ordering {
+  let (ra, rb) = (type_rank(a), type_rank(b));
+  if ra != rb {
+    return ra.cmp(&rb)
+  }
+  match (a, b) {
+    (FTerm::Atom(x), FTerm::Atom(y)) => x.cmp(y),
+    (FTerm::Reference { node: n1, ids: i1 },
+     FTerm::Reference { node: n2, ids: i2 }) =>
+      // Most significant id word is the last
+      n1.cmp(n2)
+        .then(i1.len().cmp(&i2.len()))
+        .then_with(|| i1.iter().rev().cmp(i2.iter().rev())),
+    (FTerm::Port { node: n1, id: i1 }, FTerm::Port { node: n2, id: i2 }) =>
+      n1.cmp(n2).then(i1.cmp(i2)),
+    (FTerm::Pid { node: n1, id: i1, serial: s1 },
+     FTerm::Pid { node: n2, id: i2, serial: s2 }) =>
+      n1.cmp(n2).then(s1.cmp(s2)).then(i1.cmp(i2)),
+    (FTerm::Map(x), FTerm::Map(y)) => cmp_maps(x, y, exact),
+    (FTerm::Binary(x, xbits), FTerm::Binary(y, ybits)) =>
+      cmp_bits(x, *xbits, y, *ybits),
+    _ => match ra {
+      NUMBER_RANK => cmp_numbers(a, b, exact),
+      FUN_RANK => cmp_funs(a, b, exact),
+      NIL_RANK => Ordering::Equal,
+      LIST_RANK => cmp_lists(a, b, exact),
+      _ => {
+        // Only tuples are left, possibly one of them is EmptyTuple
+        let (x, y) = (tuple_elements(a), tuple_elements(b));
+        x.len().cmp(&y.len()).then_with(|| cmp_seq(x, y, exact))
+      },
+    },
+  }
+}
+
+
+/// Compare elementwise, shorter sequence first if one is a prefix of other
+fn cmp_seq<T>(x: &[T], y: &[T], exact: bool) -> Ordering
+  where T: Borrow<FTerm>
+{
+  for (p, q) in x.iter().zip(y.iter()) {
+    let o = cmp_terms(p.borrow(), q.borrow(), exact);
+    if o != Ordering::Equal {
+      return o
+    }
+  }
+  x.len().cmp(&y.len())
+}
+
+
+fn int_value<'a>(t: &'a FTerm) -> Cow<'a, BigInt> {
+  match t {
+    FTerm::Int64(i) => Cow::Owned(BigInt::from(*i)),
+    FTerm::BigInt(b) => Cow::Borrowed(b),
+    other => panic!("{} is not an integer", other),
+  }
+}
+
+
+fn cmp_numbers(a: &FTerm, b: &FTerm, exact: bool) -> Ordering {
+  // When an integer and a float have the same value, the exact order puts
+  // the integer first
+  let tie = if exact { Ordering::Less } else { Ordering::Equal };
+  match (a, b) {
+    (FTerm::Int64(x), FTerm::Int64(y)) => x.cmp(y),
+    (FTerm::Float(x), FTerm::Float(y)) => cmp_floats(*x, *y, exact),
+    (FTerm::Float(x), _) => cmp_int_float(&int_value(b), *x).then(tie).reverse(),
+    (_, FTerm::Float(y)) => cmp_int_float(&int_value(a), *y).then(tie),
+    _ => int_value(a).cmp(&int_value(b)),
+  }
+}
+
+
+fn cmp_floats(x: f64, y: f64, exact: bool) -> Ordering {
+  match x.partial_cmp(&y) {
+    Some(Ordering::Equal) if exact =>
+      y.is_sign_negative().cmp(&x.is_sign_negative()),
+    Some(o) => o,
+    // Erlang has no NaN, order it somehow to keep the order total
+    None => x.to_bits().cmp(&y.to_bits()),
+  }
+}
+
+
+/// Compare integer with a float precisely, without rounding the integer
+fn cmp_int_float(i: &BigInt, f: f64) -> Ordering {
+  let fl = f.floor();
+  match BigInt::from_f64(fl) {
+    Some(fi) => match i.cmp(&fi) {
+      Ordering::Equal if fl != f => Ordering::Less,
+      o => o,
+    },
+    // Infinity or NaN
+    None => if f > 0.0 { Ordering::Less } else { Ordering::Greater },
+  }
+}
+
+
+/// Export funs sort before local funs
+fn cmp_funs(a: &FTerm, b: &FTerm, exact: bool) -> Ordering {
+  match (a, b) {
+    (FTerm::ExportFun { module: m1, fun: f1, arity: a1 },
+     FTerm::ExportFun { module: m2, fun: f2, arity: a2 }) =>
+      (m1, f1, a1).cmp(&(m2, f2, a2)),
+    (FTerm::ExportFun { .. }, _) => Ordering::Less,
+    (_, FTerm::ExportFun { .. }) => Ordering::Greater,
+    (FTerm::LocalFun { module: m1, index: i1, uniq: u1, env: e1 },
+     FTerm::LocalFun { module: m2, index: i2, uniq: u2, env: e2 }) =>
+      (m1, i1, u1).cmp(&(m2, i2, u2)).then_with(|| cmp_fun_envs(e1, e2, exact)),
+    _ => unreachable!(),
+  }
+}
+
+
+fn cmp_fun_envs(a: &Option<Box<FunEnv>>, b: &Option<Box<FunEnv>>,
+                exact: bool) -> Ordering {
+  match (a, b) {
+    (None, None) => Ordering::Equal,
+    (None, Some(_)) => Ordering::Less,
+    (Some(_), None) => Ordering::Greater,
+    (Some(x), Some(y)) =>
+      x.arity.cmp(&y.arity)
+        .then(x.md5.cmp(&y.md5))
+        .then_with(|| cmp_terms(&x.pid, &y.pid, exact))
+        .then_with(|| cmp_seq(&x.free_vars, &y.free_vars, exact)),
+  }
+}
+
+
+fn tuple_elements(t: &FTerm) -> &[FTerm] {
+  match t {
+    FTerm::Tuple(v) => v,
+    _ => &[],
+  }
+}
+
+
+/// Pairs ordered by key, keys are always compared exactly
+fn sorted_pairs(pairs: &[(FTerm, FTerm)]) -> Vec<&(FTerm, FTerm)> {
+  let mut sorted: Vec<_> = pairs.iter().collect();
+  sorted.sort_by(|p, q| p.0.compare_exact(&q.0));
+  sorted
+}
+
+
+/// Maps compare by size, then by keys in order, then by values in key order
+fn cmp_maps(x: &[(FTerm, FTerm)], y: &[(FTerm, FTerm)], exact: bool) -> Ordering {
+  x.len().cmp(&y.len()).then_with(|| {
+    let (xs, ys) = (sorted_pairs(x), sorted_pairs(y));
+    let keys: Vec<&FTerm> = xs.iter().map(|p| &p.0).collect();
+    let other_keys: Vec<&FTerm> = ys.iter().map(|p| &p.0).collect();
+    cmp_seq(&keys, &other_keys, true).then_with(|| {
+      let values: Vec<&FTerm> = xs.iter().map(|p| &p.1).collect();
+      let other_values: Vec<&FTerm> = ys.iter().map(|p| &p.1).collect();
+      cmp_seq(&values, &other_values, exact)
+    })
+  })
+}
+
+
+/// Elements and tail (`None` for a proper list) of a list. A string is a
+/// list of character codes.
+fn list_parts<'a>(t: &'a FTerm) -> (Vec<Cow<'a, FTerm>>, Option<&'a FTerm>) {
+  match t {
+    FTerm::List(v) => (v.iter().map(Cow::Borrowed).collect(), None),
+    FTerm::ImproperList(v, tail) =>
+      (v.iter().map(Cow::Borrowed).collect(), Some(&**tail)),
+    FTerm::String(s) =>
+      (s.chars().map(|c| Cow::Owned(FTerm::Int64(c as i64))).collect(), None),
+    _ => (Vec::new(), None),
+  }
+}
+
+
+fn tail_rank(tail: Option<&FTerm>) -> u8 {
+  tail.map_or(NIL_RANK, type_rank)
+}
+
+
+/// Lists compare head to head, when one list ends its tail is compared with
+/// the rest of the other list
+fn cmp_lists(a: &FTerm, b: &FTerm, exact: bool) -> Ordering {
+  let (xs, xtail) = list_parts(a);
+  let (ys, ytail) = list_parts(b);
+  for (x, y) in xs.iter().zip(ys.iter()) {
+    let o = cmp_terms(x, y, exact);
+    if o != Ordering::Equal {
+      return o
+    }
+  }
+  match xs.len().cmp(&ys.len()) {
+    Ordering::Less => tail_rank(xtail).cmp(&LIST_RANK),
+    Ordering::Greater => LIST_RANK.cmp(&tail_rank(ytail)),
+    Ordering::Equal => match (xtail, ytail) {
+      (Some(p), Some(q)) => cmp_terms(p, q, exact),
+      _ => tail_rank(xtail).cmp(&tail_rank(ytail)),
+    },
+  }
+}
+
+
+/// Bitstrings compare bit by bit, a prefix sorts first
+fn cmp_bits(x: &[u8], xbits: usize, y: &[u8], ybits: usize) -> Ordering {
+  let common = xbits.min(ybits);
+  let whole = common / 8;
+  x[..whole].cmp(&y[..whole])
+    .then_with(|| match common % 8 {
+      0 => Ordering::Equal,
+      rest => {
+        let mask = 0xffu8 << (8 - rest);
+        (x[whole] & mask).cmp(&(y[whole] & mask))
+      },
+    })
+    .then(xbits.cmp(&ybits))
+}
+
+
+impl PartialEq for FTerm {
+  fn eq(&self, other: &FTerm) -> bool {
+    self.exact_equals(other)
+  }
+}
+
+
+impl Eq for FTerm {}
+
+
+impl PartialOrd for FTerm {
+  fn partial_cmp(&self, other: &FTerm) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+
+impl Ord for FTerm {
+  fn cmp(&self, other: &FTerm) -> Ordering {
+    self.compare_exact(other)
+  }
+}
+
+
+/// Terms which are `=:=` hash the same: small BigInt as Int64, strings as
+/// lists, maps regardless of pair order.
+impl Hash for FTerm {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    let rank = type_rank(self);
+    rank.hash(state);
+    if rank == NIL_RANK {
+      return
+    }
+    match self {
+      FTerm::Int64(i) => i.hash(state),
+      FTerm::BigInt(b) => match b.to_i64() {
+        Some(i) => i.hash(state),
+        None => b.hash(state),
+      },
+      FTerm::Float(f) => f.to_bits().hash(state),
+      FTerm::Atom(s) => s.hash(state),
+      FTerm::Reference { node, ids } => {
+        node.hash(state);
+        ids.hash(state);
+      },
+      FTerm::Port { node, id } => {
+        node.hash(state);
+        id.hash(state);
+      },
+      FTerm::Pid { node, id, serial } => {
+        node.hash(state);
+        id.hash(state);
+        serial.hash(state);
+      },
+      FTerm::ExportFun { module, fun, arity } => {
+        module.hash(state);
+        fun.hash(state);
+        arity.hash(state);
+      },
+      FTerm::LocalFun { module, index, uniq, env } => {
+        module.hash(state);
+        index.hash(state);
+        uniq.hash(state);
+        env.hash(state);
+      },
+      FTerm::Tuple(_) | FTerm::EmptyTuple => tuple_elements(self).hash(state),
+      FTerm::Map(pairs) => {
+        pairs.len().hash(state);
+        for (k, v) in sorted_pairs(pairs) {
+          k.hash(state);
+          v.hash(state);
+        }
+      },
+      FTerm::EmptyList => {},
+      FTerm::String(_) | FTerm::List(_) | FTerm::ImproperList(_, _) => {
+        let (elements, tail) = list_parts(self);
+        elements.hash(state);
+        tail.hash(state);
+      },
+      FTerm::Binary(data, bits) => {
+        bits.hash(state);
+        data[..bits / 8].hash(state);
+        if bits % 8 != 0 {
+          (data[bits / 8] & (0xffu8 << (8 - bits % 8))).hash(state);
+        }
+      },
+    }
+  }
+}